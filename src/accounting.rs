@@ -0,0 +1,176 @@
+use std::collections::HashSet;
+use std::{fmt, str};
+
+use seq_io::fastq::{Reader, Record};
+use serde_json::{json, Value};
+
+use crate::header::HeaderParser;
+use crate::input::InputReads;
+use crate::paf::PafReads;
+use crate::reader;
+
+fn count_not_in<'a>(names: impl Iterator<Item = &'a String>, set: &HashSet<String>) -> u64 {
+    names.filter(|name| !set.contains(*name)).count() as u64
+}
+
+/// How a tool's output reads line up with its input reads and the truth set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputAccounting {
+    pub input_reads: u64,
+    pub output_records: u64,
+    /// Output records whose header the parser couldn't read.
+    pub unparsed: u64,
+    /// Repeat output records for a read (or segment) already seen.
+    pub duplicates: u64,
+    /// Reads in the output that were never in the input.
+    pub extras: u64,
+    /// Input reads with nothing in the output.
+    pub drops: u64,
+    /// Truth reads that weren't in the input, so can only ever be missing.
+    pub truth_not_in_input: u64,
+    pub input_without_truth: u64,
+}
+
+impl OutputAccounting {
+    /// Only the output is read here, the input names come ready-made.
+    pub fn new(
+        input_reads: &InputReads,
+        output: &str,
+        paf_reads: &PafReads,
+        header_parser: &dyn HeaderParser,
+    ) -> OutputAccounting {
+        let input_names = &input_reads.names;
+
+        let mut output_records = 0;
+        let mut unparsed = 0;
+        let mut duplicates = 0;
+        let mut seen: HashSet<(String, Option<String>)> = HashSet::new();
+        let mut output_names: HashSet<String> = HashSet::new();
+        let mut reader = Reader::new(reader::open(output));
+        while let Some(record) = reader.next() {
+            let record = record.expect("Error reading record");
+            let header = str::from_utf8(record.head()).expect("Header is not valid UTF-8");
+            output_records += 1;
+
+            let Some(parsed) = header_parser.parse(header) else {
+                unparsed += 1;
+                continue;
+            };
+            if !seen.insert((parsed.name.to_string(), parsed.segment.map(str::to_string))) {
+                duplicates += 1;
+            }
+            output_names.insert(parsed.name.to_string());
+        }
+
+        OutputAccounting {
+            input_reads: input_names.len() as u64,
+            output_records,
+            unparsed,
+            duplicates,
+            extras: count_not_in(output_names.iter(), input_names),
+            drops: count_not_in(input_names.iter(), &output_names),
+            truth_not_in_input: count_not_in(paf_reads.map.keys(), input_names),
+            input_without_truth: input_names
+                .iter()
+                .filter(|name| !paf_reads.map.contains_key(*name))
+                .count() as u64,
+        }
+    }
+
+    /// Every output record is a distinct input read (or segment of one).
+    /// Dropped reads don't unbalance it, they're scored as missing.
+    pub fn balanced(&self) -> bool {
+        self.unparsed == 0 && self.duplicates == 0 && self.extras == 0
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "input_reads": self.input_reads,
+            "output_records": self.output_records,
+            "unparsed": self.unparsed,
+            "duplicates": self.duplicates,
+            "extras": self.extras,
+            "drops": self.drops,
+            "truth_not_in_input": self.truth_not_in_input,
+            "input_without_truth": self.input_without_truth,
+            "balanced": self.balanced(),
+        })
+    }
+}
+
+impl fmt::Display for OutputAccounting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "input reads           {}", self.input_reads)?;
+        writeln!(f, "output records        {}", self.output_records)?;
+        writeln!(f, "  unparsed            {}", self.unparsed)?;
+        writeln!(f, "  duplicates          {}", self.duplicates)?;
+        writeln!(f, "  extras              {}", self.extras)?;
+        writeln!(f, "dropped reads         {}", self.drops)?;
+        writeln!(f, "truth not in input    {}", self.truth_not_in_input)?;
+        write!(f, "input without truth   {}", self.input_without_truth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::PychopperHeader;
+    use crate::paf::TruthAlignment;
+
+    #[test]
+    fn accounts_for_every_output_record() {
+        let output = std::env::temp_dir().join(format!(
+            "restrander-testing-rig-accounting-{}.fq",
+            std::process::id()
+        ));
+        let records = [
+            "0:10|r1 strand=+",
+            "10:20|r1 strand=-",
+            "10:20|r1 strand=-",
+            "0:10|r2 strand=+",
+            "0:10|r9 strand=+",
+            "garbage",
+        ];
+        let fastq = records
+            .iter()
+            .map(|header| format!("@{header}\nACGT\n+\nIIII\n"))
+            .collect::<String>();
+        std::fs::write(&output, fastq).unwrap();
+
+        let input_reads = InputReads {
+            names: ["r1", "r2", "r3", "r4"].map(str::to_string).into(),
+            ..InputReads::default()
+        };
+        let mut paf_reads = PafReads::new();
+        for name in ["r1", "r3", "r5"] {
+            let alignment = TruthAlignment {
+                target_name: "tx".to_string(),
+                query_length: 4,
+            };
+            paf_reads.insert(name.to_string(), alignment, '+');
+        }
+
+        let accounting = OutputAccounting::new(
+            &input_reads,
+            output.to_str().unwrap(),
+            &paf_reads,
+            &PychopperHeader,
+        );
+        std::fs::remove_file(output).unwrap();
+
+        assert_eq!(
+            accounting,
+            OutputAccounting {
+                input_reads: 4,
+                output_records: 6,
+                unparsed: 1,
+                duplicates: 1,
+                extras: 1,
+                drops: 2,
+                truth_not_in_input: 1,
+                input_without_truth: 2,
+            }
+        );
+        assert!(!accounting.balanced());
+    }
+}
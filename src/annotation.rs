@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use crate::config::{AntisensePolicy, IntergenicPolicy, TruthConfig};
+use crate::paf::PafRecord;
+use crate::reader;
+
+/// A gene's span on the genome.
+#[derive(Debug, Clone)]
+pub struct Gene {
+    pub start: u64,
+    pub end: u64,
+    pub strand: char,
+}
+
+/// Genes from a GTF or GFF3 file, indexed by chromosome for overlap queries.
+pub struct Annotation {
+    chromosomes: HashMap<String, Vec<Gene>>,
+    longest_gene: u64,
+}
+
+impl Annotation {
+    /// Load the gene features from a GTF/GFF3 file. Annotations without any
+    /// gene lines fall back to their transcripts.
+    pub fn load(filename: &str) -> Annotation {
+        Annotation::from_reader(reader::open(filename))
+    }
+
+    fn from_reader(file: impl BufRead) -> Annotation {
+        let mut genes = vec![];
+        let mut transcripts = vec![];
+        for line in file.lines() {
+            let line = line.expect("Failed to read annotation line");
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+
+            let fields = line.split('\t').collect::<Vec<_>>();
+            if fields.len() < 9 {
+                panic!("Annotation line has {} fields: {}", fields.len(), line);
+            }
+
+            let features = match fields[2] {
+                "gene" => &mut genes,
+                "transcript" | "mRNA" => &mut transcripts,
+                _ => continue,
+            };
+            let strand = match fields[6] {
+                "+" => '+',
+                "-" => '-',
+                _ => continue,
+            };
+            let position = |i: usize| -> u64 {
+                fields[i]
+                    .parse()
+                    .unwrap_or_else(|_| panic!("Bad position in annotation line: {}", line))
+            };
+
+            features.push((
+                fields[0].to_string(),
+                Gene {
+                    // GTF/GFF are 1-based inclusive, PAF is 0-based half-open
+                    start: position(3) - 1,
+                    end: position(4),
+                    strand,
+                },
+            ));
+        }
+
+        if genes.is_empty() {
+            genes = transcripts;
+        }
+
+        let longest_gene = genes
+            .iter()
+            .map(|(_, gene)| gene.end - gene.start)
+            .max()
+            .unwrap_or(0);
+
+        let mut chromosomes: HashMap<String, Vec<Gene>> = HashMap::new();
+        for (chromosome, gene) in genes {
+            chromosomes.entry(chromosome).or_default().push(gene);
+        }
+        for genes in chromosomes.values_mut() {
+            genes.sort_by_key(|gene| gene.start);
+        }
+
+        Annotation {
+            chromosomes,
+            longest_gene,
+        }
+    }
+
+    /// All genes overlapping the half-open interval, with their overlap in bases.
+    pub fn overlapping(&self, chromosome: &str, start: u64, end: u64) -> Vec<(&Gene, u64)> {
+        let Some(genes) = self.chromosomes.get(chromosome) else {
+            return vec![];
+        };
+
+        // genes are sorted by start, so only look back as far as the longest gene could reach
+        let last = genes.partition_point(|gene| gene.start < end);
+        let first = genes.partition_point(|gene| gene.start + self.longest_gene <= start);
+        genes[first..last.max(first)]
+            .iter()
+            .filter(|gene| gene.end > start)
+            .map(|gene| (gene, gene.end.min(end) - gene.start.max(start)))
+            .collect()
+    }
+
+    /// The strand of the transcript a genome alignment came from, or None if
+    /// the read can't be placed on a gene under the given policies.
+    ///
+    /// Overlapping and nested genes on the same strand agree, so any overlap
+    /// places the read. Genes on both strands are settled by the antisense
+    /// policy alone: the read is dropped (and counted under the annotation
+    /// filter), or goes to the strand with more overlapping bases. It is
+    /// never kept with an ambiguous strand.
+    pub fn transcript_strand(&self, record: &PafRecord, config: &TruthConfig) -> Option<char> {
+        let genes = self.overlapping(&record.target_name, record.target_start, record.target_end);
+
+        let gene_strand = if genes.is_empty() {
+            match config.intergenic {
+                IntergenicPolicy::Drop => return None,
+                IntergenicPolicy::Alignment => return Some(record.strand),
+            }
+        } else if genes
+            .iter()
+            .all(|(gene, _)| gene.strand == genes[0].0.strand)
+        {
+            genes[0].0.strand
+        } else {
+            match config.antisense {
+                AntisensePolicy::Drop => return None,
+                AntisensePolicy::MostOverlap => {
+                    let overlap = |strand: char| -> u64 {
+                        genes
+                            .iter()
+                            .filter(|(gene, _)| gene.strand == strand)
+                            .map(|(_, overlap)| overlap)
+                            .sum()
+                    };
+                    match overlap('+').cmp(&overlap('-')) {
+                        std::cmp::Ordering::Greater => '+',
+                        std::cmp::Ordering::Less => '-',
+                        std::cmp::Ordering::Equal => return None,
+                    }
+                }
+            }
+        };
+
+        // a read aligned to the same strand as its gene is in transcript orientation
+        Some(if record.strand == gene_strand {
+            '+'
+        } else {
+            '-'
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flags::Flags;
+
+    const GTF: &str = "\
+#comment
+chr1\tx\tgene\t1001\t2000\t.\t+\t.\tgene_id \"A\";
+chr1\tx\tgene\t1501\t3000\t.\t+\t.\tgene_id \"B\";
+chr1\tx\tgene\t1601\t1700\t.\t+\t.\tgene_id \"C\";
+chr1\tx\tgene\t5001\t6000\t.\t+\t.\tgene_id \"D\";
+chr1\tx\tgene\t5401\t5700\t.\t-\t.\tgene_id \"E\";
+chr1\tx\texon\t8001\t9000\t.\t-\t.\tgene_id \"F\";
+chr2\tx\tgene\t1\t100000\t.\t-\t.\tgene_id \"G\";
+";
+
+    fn annotation() -> Annotation {
+        Annotation::from_reader(GTF.as_bytes())
+    }
+
+    fn truth_config(flags: &[&str]) -> TruthConfig {
+        let args = flags.iter().map(|flag| flag.to_string()).collect();
+        TruthConfig::from_flags(&Flags::split_args(args).1)
+    }
+
+    fn alignment(target: &str, start: u64, end: u64, strand: char) -> PafRecord {
+        PafRecord::from_paf_line(&format!(
+            "r1\t1000\t0\t1000\t{strand}\t{target}\t200000\t{start}\t{end}\t1000\t1000\t60"
+        ))
+    }
+
+    fn overlaps(chromosome: &str, start: u64, end: u64) -> Vec<(u64, u64, u64)> {
+        let annotation = annotation();
+        let mut overlaps = annotation
+            .overlapping(chromosome, start, end)
+            .into_iter()
+            .map(|(gene, overlap)| (gene.start, gene.end, overlap))
+            .collect::<Vec<_>>();
+        overlaps.sort();
+        overlaps
+    }
+
+    #[test]
+    fn finds_overlapping_and_nested_genes() {
+        assert_eq!(
+            overlaps("chr1", 1650, 1660),
+            vec![(1000, 2000, 10), (1500, 3000, 10), (1600, 1700, 10)]
+        );
+        assert_eq!(overlaps("chr1", 2500, 4000), vec![(1500, 3000, 500)]);
+        assert!(overlaps("chr3", 0, 100).is_empty());
+    }
+
+    #[test]
+    fn intervals_are_half_open() {
+        // GTF 1001-2000 is 0-based 1000..2000
+        assert!(overlaps("chr1", 0, 1000).is_empty());
+        assert_eq!(overlaps("chr1", 999, 1001), vec![(1000, 2000, 1)]);
+        assert_eq!(overlaps("chr1", 3000, 4000), vec![]);
+    }
+
+    #[test]
+    fn long_genes_are_found_from_far_away() {
+        assert_eq!(overlaps("chr2", 90000, 90010), vec![(0, 100000, 10)]);
+    }
+
+    #[test]
+    fn only_gene_features_are_used() {
+        assert!(overlaps("chr1", 8500, 8600).is_empty());
+    }
+
+    #[test]
+    fn same_strand_genes_agree() {
+        let config = truth_config(&[]);
+        let annotation = annotation();
+
+        assert_eq!(
+            annotation.transcript_strand(&alignment("chr1", 1650, 1660, '+'), &config),
+            Some('+')
+        );
+        assert_eq!(
+            annotation.transcript_strand(&alignment("chr1", 1650, 1660, '-'), &config),
+            Some('-')
+        );
+        assert_eq!(
+            annotation.transcript_strand(&alignment("chr2", 10, 20, '+'), &config),
+            Some('-')
+        );
+    }
+
+    #[test]
+    fn opposite_strand_genes_follow_antisense_policy() {
+        let annotation = annotation();
+        let drop = truth_config(&[]);
+        let most_overlap = truth_config(&["--antisense=most-overlap"]);
+
+        // mostly in D (+), partly in the nested E (-)
+        let mostly_forward = alignment("chr1", 5300, 5500, '-');
+        assert_eq!(annotation.transcript_strand(&mostly_forward, &drop), None);
+        assert_eq!(
+            annotation.transcript_strand(&mostly_forward, &most_overlap),
+            Some('-')
+        );
+
+        // inside E, so equal overlap with D and E
+        let tied = alignment("chr1", 5450, 5550, '+');
+        assert_eq!(annotation.transcript_strand(&tied, &most_overlap), None);
+    }
+
+    #[test]
+    fn intergenic_reads_follow_intergenic_policy() {
+        let annotation = annotation();
+        let record = alignment("chr1", 4000, 4500, '-');
+
+        let drop = truth_config(&[]);
+        let fallback = truth_config(&["--intergenic=alignment"]);
+        assert_eq!(annotation.transcript_strand(&record, &drop), None);
+        assert_eq!(annotation.transcript_strand(&record, &fallback), Some('-'));
+    }
+}
@@ -0,0 +1,259 @@
+use std::fmt;
+
+use serde_json::{json, Value};
+
+use crate::metrics::text_value;
+use crate::paf::PafRecord;
+
+/// The artefacts restrander reports when `report-artefacts` is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Artefact {
+    TsoTso,
+    RtpRtp,
+}
+
+impl Artefact {
+    pub fn from_label(label: &str) -> Option<Artefact> {
+        match label {
+            "TSO-TSO" => Some(Artefact::TsoTso),
+            "RTP-RTP" => Some(Artefact::RtpRtp),
+            _ => None,
+        }
+    }
+}
+
+/// The part of the read one alignment covers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryBlock {
+    start: u64,
+    end: u64,
+    length: u64,
+    strand: char,
+    secondary: bool,
+    /// SAM/BAM primary alignments list their supplementary alignments in an `SA` tag.
+    has_supplementary: bool,
+}
+
+impl QueryBlock {
+    pub fn new(record: &PafRecord) -> QueryBlock {
+        QueryBlock {
+            start: record.query_start,
+            end: record.query_end,
+            length: record.query_length,
+            strand: record.strand,
+            secondary: record
+                .tag("tp")
+                .is_some_and(|tag| tag.value == "S" || tag.value == "s"),
+            has_supplementary: record.tag("SA").is_some(),
+        }
+    }
+
+    fn coverage(&self) -> f64 {
+        if self.length == 0 {
+            return 0.0;
+        }
+        (self.end - self.start) as f64 / self.length as f64
+    }
+
+    /// The two blocks cover different parts of the read, overlapping by
+    /// less than half of the shorter one.
+    fn disjoint(&self, other: &QueryBlock) -> bool {
+        let overlap = self
+            .end
+            .min(other.end)
+            .saturating_sub(self.start.max(other.start));
+        let shorter = (self.end - self.start).min(other.end - other.start);
+        overlap * 2 < shorter
+    }
+}
+
+/// Whether a read's alignments look like a chimera: either it has a
+/// supplementary alignment, or no alignment covers much of it and two
+/// alignments cover different parts of it on opposite strands.
+pub fn is_chimeric(blocks: &[QueryBlock], max_coverage: f64) -> bool {
+    let pairs = || {
+        blocks.iter().enumerate().flat_map(move |(i, a)| {
+            blocks[i + 1..]
+                .iter()
+                .map(move |b| (a, b))
+                .filter(|(a, b)| a.disjoint(b))
+        })
+    };
+
+    // supplementary alignments cover other parts of the read: extra primary
+    // lines in PAF, and supplementary records in SAM/BAM
+    let supplementary = blocks.iter().any(|block| block.has_supplementary)
+        || pairs().any(|(a, b)| !a.secondary && !b.secondary);
+
+    let low_coverage = blocks.iter().all(|block| block.coverage() < max_coverage);
+    let opposite_strands = pairs().any(|(a, b)| a.strand != b.strand);
+
+    supplementary || (low_coverage && opposite_strands)
+}
+
+/// How well a tool's artefact labels pick out the chimeric reads, over the
+/// output reads with a truth entry.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ArtefactResult {
+    /// Whether the tool can label artefacts. Precision and recall are
+    /// undefined for tools that can't.
+    pub reported: bool,
+    pub true_positives: u64,
+    pub false_positives: u64,
+    pub false_negatives: u64,
+    pub true_negatives: u64,
+    pub tso_tso: u64,
+    pub rtp_rtp: u64,
+}
+
+impl ArtefactResult {
+    pub fn add(&mut self, chimeric: bool, artefact: Option<Artefact>) {
+        match artefact {
+            Some(Artefact::TsoTso) => self.tso_tso += 1,
+            Some(Artefact::RtpRtp) => self.rtp_rtp += 1,
+            None => (),
+        }
+        match (chimeric, artefact.is_some()) {
+            (true, true) => self.true_positives += 1,
+            (false, true) => self.false_positives += 1,
+            (true, false) => self.false_negatives += 1,
+            (false, false) => self.true_negatives += 1,
+        }
+    }
+
+    pub fn merge(&mut self, other: &ArtefactResult) {
+        self.true_positives += other.true_positives;
+        self.false_positives += other.false_positives;
+        self.false_negatives += other.false_negatives;
+        self.true_negatives += other.true_negatives;
+        self.tso_tso += other.tso_tso;
+        self.rtp_rtp += other.rtp_rtp;
+    }
+
+    pub fn new(reported: bool) -> ArtefactResult {
+        ArtefactResult {
+            reported,
+            ..ArtefactResult::default()
+        }
+    }
+
+    /// Undefined (NaN, see `metrics::csv_value`) if the tool doesn't label
+    /// artefacts or the denominator is zero.
+    fn rate(&self, misses: u64) -> f64 {
+        let total = self.true_positives + misses;
+        if !self.reported || total == 0 {
+            return f64::NAN;
+        }
+        self.true_positives as f64 / total as f64
+    }
+
+    pub fn precision(&self) -> f64 {
+        self.rate(self.false_positives)
+    }
+
+    pub fn recall(&self) -> f64 {
+        self.rate(self.false_negatives)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "true_positives": self.true_positives,
+            "false_positives": self.false_positives,
+            "false_negatives": self.false_negatives,
+            "true_negatives": self.true_negatives,
+            "tso_tso": self.tso_tso,
+            "rtp_rtp": self.rtp_rtp,
+            "precision": self.precision(),
+            "recall": self.recall(),
+        })
+    }
+}
+
+impl fmt::Display for ArtefactResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "artefacts TSO-TSO {} RTP-RTP {}\nartefact precision {}\nartefact recall {}",
+            self.tso_tso,
+            self.rtp_rtp,
+            text_value(self.precision()),
+            text_value(self.recall())
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(start: u64, end: u64, strand: char, tags: &[&str]) -> QueryBlock {
+        let mut line =
+            format!("r1\t1000\t{start}\t{end}\t{strand}\ttx\t5000\t0\t100\t100\t100\t60");
+        for tag in tags {
+            line.push('\t');
+            line.push_str(tag);
+        }
+        QueryBlock::new(&PafRecord::from_paf_line(&line))
+    }
+
+    #[test]
+    fn finds_chimeras() {
+        // one alignment covering the read
+        assert!(!is_chimeric(&[block(0, 950, '+', &["tp:A:P"])], 0.8));
+        // SAM supplementary alignments
+        assert!(is_chimeric(&[block(0, 950, '+', &["SA:Z:x"])], 0.8));
+        // two primary lines on different parts of the read
+        assert!(is_chimeric(
+            &[
+                block(0, 500, '+', &["tp:A:P"]),
+                block(500, 1000, '+', &["tp:A:P"])
+            ],
+            0.8
+        ));
+        // a secondary alignment isn't a second part
+        assert!(!is_chimeric(
+            &[
+                block(0, 500, '+', &["tp:A:P"]),
+                block(500, 1000, '+', &["tp:A:S"])
+            ],
+            0.8
+        ));
+        // low coverage on opposite strands, even without primary tags
+        assert!(is_chimeric(
+            &[block(0, 400, '+', &[]), block(500, 900, '-', &[])],
+            0.8
+        ));
+        // overlapping alignments aren't different parts
+        assert!(!is_chimeric(
+            &[block(0, 400, '+', &[]), block(100, 500, '-', &[])],
+            0.8
+        ));
+    }
+
+    #[test]
+    fn scores_artefact_labels() {
+        let mut result = ArtefactResult::new(true);
+        result.add(true, Some(Artefact::TsoTso));
+        result.add(true, Some(Artefact::RtpRtp));
+        result.add(true, None);
+        result.add(false, Some(Artefact::TsoTso));
+        result.add(false, None);
+
+        assert_eq!((result.tso_tso, result.rtp_rtp), (2, 1));
+        assert_eq!(result.precision(), 2.0 / 3.0);
+        assert_eq!(result.recall(), 2.0 / 3.0);
+    }
+
+    #[test]
+    fn undefined_without_labels_or_chimeras() {
+        let mut unreported = ArtefactResult::new(false);
+        unreported.add(true, None);
+        assert!(unreported.precision().is_nan());
+        assert!(unreported.recall().is_nan());
+
+        let mut no_chimeras = ArtefactResult::new(true);
+        no_chimeras.add(false, None);
+        assert!(no_chimeras.precision().is_nan());
+        assert!(no_chimeras.recall().is_nan());
+    }
+}
@@ -0,0 +1,333 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::UNIX_EPOCH;
+
+use crate::config::TruthConfig;
+use crate::paf::{FilterCounts, PafReads, TruthAlignment};
+
+const MAGIC: &[u8; 4] = b"RTRC";
+const VERSION: u32 = 1;
+/// Only this many truth sets are kept, the least recently written go first.
+const MAX_ENTRIES: usize = 8;
+
+/// Identifies a file by its path, size and modification time.
+fn fingerprint(filename: &str) -> io::Result<String> {
+    let path = fs::canonicalize(filename)?;
+    let metadata = fs::metadata(&path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+
+    Ok(format!(
+        "{}:{}:{}",
+        path.display(),
+        metadata.len(),
+        modified
+    ))
+}
+
+/// Everything the truth set depends on: the truth file, the annotation
+/// (if any), and the truth config itself.
+fn cache_key(filename: &str, config: &TruthConfig) -> io::Result<String> {
+    let annotation = match &config.annotation {
+        Some(annotation) => fingerprint(annotation)?,
+        None => "".to_string(),
+    };
+    let config = TruthConfig {
+        cache: None,
+        ..config.clone()
+    };
+
+    Ok(format!(
+        "{}|{}|{:?}",
+        fingerprint(filename)?,
+        annotation,
+        config
+    ))
+}
+
+fn cache_path(cache_dir: &str, key: &str) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    Path::new(cache_dir).join(format!("{:016x}.truth", hasher.finish()))
+}
+
+/// Look up a previously parsed truth set. Any problem reading the cache,
+/// or a stale entry, is treated as a miss.
+pub fn get(cache_dir: &str, filename: &str, config: &TruthConfig) -> Option<PafReads> {
+    let key = cache_key(filename, config).ok()?;
+    let file = File::open(cache_path(cache_dir, &key)).ok()?;
+
+    let mut decoder = Decoder {
+        inner: BufReader::new(file),
+    };
+    decoder.read_reads(&key).ok()
+}
+
+/// Save a parsed truth set for next time. Failing to write the cache
+/// isn't fatal, we'll just parse again.
+pub fn put(cache_dir: &str, filename: &str, config: &TruthConfig, reads: &PafReads) {
+    let result = cache_key(filename, config).and_then(|key| {
+        fs::create_dir_all(cache_dir)?;
+
+        // write to a temporary file first so a crash can't leave a partial cache
+        let path = cache_path(cache_dir, &key);
+        let temp_path = path.with_extension(format!("{}.tmp", process::id()));
+        let mut encoder = Encoder {
+            inner: BufWriter::new(File::create(&temp_path)?),
+        };
+        encoder.write_reads(&key, reads)?;
+        encoder.inner.flush()?;
+        fs::rename(temp_path, path)?;
+        prune(cache_dir)
+    });
+
+    if let Err(e) = result {
+        eprintln!("Couldn't write truth cache to {}: {}", cache_dir, e);
+    }
+}
+
+/// Remove all but the most recently written truth sets.
+fn prune(cache_dir: &str) -> io::Result<()> {
+    let mut entries = vec![];
+    for entry in fs::read_dir(cache_dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "truth")
+        {
+            entries.push((fs::metadata(&path)?.modified()?, path));
+        }
+    }
+
+    entries.sort();
+    let excess = entries.len().saturating_sub(MAX_ENTRIES);
+    for (_, path) in entries.into_iter().take(excess) {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+struct Encoder<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> Encoder<W> {
+    fn u8(&mut self, value: u8) -> io::Result<()> {
+        self.inner.write_all(&[value])
+    }
+
+    fn u32(&mut self, value: u32) -> io::Result<()> {
+        self.inner.write_all(&value.to_le_bytes())
+    }
+
+    fn u64(&mut self, value: u64) -> io::Result<()> {
+        self.inner.write_all(&value.to_le_bytes())
+    }
+
+    fn char(&mut self, value: char) -> io::Result<()> {
+        self.u32(value as u32)
+    }
+
+    fn string(&mut self, value: &str) -> io::Result<()> {
+        self.u32(value.len() as u32)?;
+        self.inner.write_all(value.as_bytes())
+    }
+
+    fn filter_counts(&mut self, counts: &FilterCounts) -> io::Result<()> {
+        self.u64(counts.mapq)?;
+        self.u64(counts.identity)?;
+        self.u64(counts.query_coverage)?;
+        self.u64(counts.multimapper)?;
+        self.u64(counts.annotation)?;
+        self.u64(counts.ts_tag)
+    }
+
+    fn alignment(&mut self, alignment: &TruthAlignment) -> io::Result<()> {
+        self.string(&alignment.target_name)?;
+        self.u64(alignment.query_length)
+    }
+
+    fn write_reads(&mut self, key: &str, reads: &PafReads) -> io::Result<()> {
+        self.inner.write_all(MAGIC)?;
+        self.u32(VERSION)?;
+        self.string(key)?;
+        self.filter_counts(&reads.filtered)?;
+
+        // every truth entry has an alignment, so store the truth strand alongside it
+        self.u64(reads.records.len() as u64)?;
+        for (name, alignment) in &reads.records {
+            self.string(name)?;
+            self.char(reads.get(name))?;
+            self.u8(reads.chimeric.contains(name) as u8)?;
+            self.alignment(alignment)?;
+        }
+        Ok(())
+    }
+}
+
+struct Decoder<R: Read> {
+    inner: R,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl<R: Read> Decoder<R> {
+    fn bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.inner.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    fn char(&mut self) -> io::Result<char> {
+        char::from_u32(self.u32()?).ok_or_else(|| invalid("bad char"))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let mut buf = vec![0; self.u32()? as usize];
+        self.inner.read_exact(&mut buf)?;
+        String::from_utf8(buf).map_err(|_| invalid("bad string"))
+    }
+
+    fn filter_counts(&mut self) -> io::Result<FilterCounts> {
+        Ok(FilterCounts {
+            mapq: self.u64()?,
+            identity: self.u64()?,
+            query_coverage: self.u64()?,
+            multimapper: self.u64()?,
+            annotation: self.u64()?,
+            ts_tag: self.u64()?,
+        })
+    }
+
+    fn alignment(&mut self) -> io::Result<TruthAlignment> {
+        Ok(TruthAlignment {
+            target_name: self.string()?,
+            query_length: self.u64()?,
+        })
+    }
+
+    fn read_reads(&mut self, key: &str) -> io::Result<PafReads> {
+        if &self.bytes::<4>()? != MAGIC || self.u32()? != VERSION {
+            return Err(invalid("not a truth cache"));
+        }
+        if self.string()? != key {
+            return Err(invalid("stale truth cache"));
+        }
+
+        let mut reads = PafReads::new();
+        reads.filtered = self.filter_counts()?;
+        for _ in 0..self.u64()? {
+            let name = self.string()?;
+            let strand = self.char()?;
+            if self.u8()? != 0 {
+                reads.chimeric.insert(name.clone());
+            }
+            let alignment = self.alignment()?;
+            reads.insert(name, alignment, strand);
+        }
+        Ok(reads)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flags::Flags;
+    use crate::paf;
+
+    /// A fresh directory under the system temp dir, unique to this test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("restrander-testing-rig-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn truth_config(cache_dir: &Path) -> TruthConfig {
+        let args = vec![format!("--truth-cache={}", cache_dir.display())];
+        TruthConfig::from_flags(&Flags::split_args(args).1)
+    }
+
+    #[test]
+    fn round_trips_truth_sets() {
+        let dir = temp_dir("cache-round-trip");
+        let paf_file = dir.join("truth.paf");
+        fs::write(
+            &paf_file,
+            "r1\t1000\t0\t900\t+\ttx1\t5000\t0\t900\t900\t900\t60\ttp:A:P\n\
+             r2\t800\t0\t300\t+\ttx2\t5000\t0\t300\t300\t300\t60\ttp:A:P\n\
+             r2\t800\t400\t800\t-\ttx3\t5000\t0\t400\t400\t400\t60\ttp:A:P\n\
+             r3\t500\t0\t500\t-\ttx1\t5000\t0\t500\t500\t500\t0\ttp:A:P\n",
+        )
+        .unwrap();
+        let filename = paf_file.to_str().unwrap();
+        let cache_dir = dir.join("cache");
+        let config = TruthConfig {
+            min_mapq: 1,
+            ..truth_config(&cache_dir)
+        };
+        let cache_dir = cache_dir.to_str().unwrap();
+
+        let reads = paf::from_records(paf::records(filename), &config, None);
+        assert_eq!(reads.size, 2);
+        assert!(reads.chimeric.contains("r2"));
+        assert_eq!(reads.filtered.mapq, 1);
+
+        assert!(get(cache_dir, filename, &config).is_none());
+        put(cache_dir, filename, &config, &reads);
+        assert_eq!(get(cache_dir, filename, &config), Some(reads));
+
+        // a different config is a different truth set
+        let other = TruthConfig {
+            min_mapq: 0,
+            ..config.clone()
+        };
+        assert!(get(cache_dir, filename, &other).is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_a_bounded_number_of_entries() {
+        let dir = temp_dir("cache-prune");
+        let cache_dir = dir.join("cache");
+        let config = truth_config(&cache_dir);
+        let cache_dir = cache_dir.to_str().unwrap();
+
+        for i in 0..MAX_ENTRIES + 3 {
+            let paf_file = dir.join(format!("truth{i}.paf"));
+            fs::write(&paf_file, "").unwrap();
+            put(
+                cache_dir,
+                paf_file.to_str().unwrap(),
+                &config,
+                &PafReads::new(),
+            );
+        }
+
+        assert_eq!(fs::read_dir(cache_dir).unwrap().count(), MAX_ENTRIES);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
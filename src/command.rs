@@ -0,0 +1,43 @@
+use std::process::Command;
+
+/// Run an external tool to completion. A tool that fails would leave a stale
+/// or partial output file behind, so panic with its stderr rather than score it.
+pub fn run(name: &str, command: &mut Command) {
+    let output = command
+        .output()
+        .unwrap_or_else(|e| panic!("{} failed to start: {}", name, e));
+
+    if !output.status.success() {
+        panic!(
+            "{} failed ({}):\n{}",
+            name,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn succeeds_quietly() {
+        run("true", &mut Command::new("true"));
+    }
+
+    #[test]
+    #[should_panic(expected = "oops")]
+    fn panics_with_stderr_on_failure() {
+        run(
+            "sh",
+            Command::new("sh").args(["-c", "echo oops >&2; exit 3"]),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "failed to start")]
+    fn panics_if_missing() {
+        run("missing", &mut Command::new("/nonexistent/tool"));
+    }
+}
@@ -1,10 +1,11 @@
-use std::{collections::HashSet, fs::File, io::Write};
+use std::{collections::HashSet, fs, fs::File, io::Write};
 
 use itertools::Itertools;
 
 use crate::{
     config::{GenericProgramConfig, SpecificProgramConfig},
     fastq::{self, CategorisedReads},
+    header::HeaderParser,
     paf::PafReads,
     pychopper, restrander,
 };
@@ -21,7 +22,7 @@ fn custom_difference(s1: &HashSet<String>, s2: &HashSet<String>) -> Vec<String>
 
 fn string_vec_to_file(filename: &str, strings: &[String]) {
     File::create(filename)
-        .expect(&format!("Failed to create file {}", filename))
+        .unwrap_or_else(|_| panic!("Failed to create file {}", filename))
         .write_all(strings.join("\n").as_bytes())
         .expect("Failed to write to file!");
 }
@@ -50,8 +51,13 @@ impl Venn {
     fn new_ambiguous(restrander: &CategorisedReads, pychopper: &CategorisedReads) -> Venn {
         Venn::new(&restrander.ambiguous, &pychopper.ambiguous)
     }
+    fn new_untruthed(restrander: &CategorisedReads, pychopper: &CategorisedReads) -> Venn {
+        Venn::new(&restrander.untruthed, &pychopper.untruthed)
+    }
 
     fn to_files(&self, directory: &str) {
+        fs::create_dir_all(directory)
+            .unwrap_or_else(|e| panic!("Failed to create directory {}: {}", directory, e));
         string_vec_to_file(
             &format!("{}/intersection.csv", directory),
             &self.intersection,
@@ -67,6 +73,7 @@ impl Venn {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn compare(
     input_fastq: &str,
     restrander_config: &str,
@@ -74,9 +81,13 @@ pub fn compare(
     paf_reads: &PafReads,
     temp_fastq: &str,
     output_directory: &str,
+    restrander_header: &dyn HeaderParser,
+    pychopper_header: &dyn HeaderParser,
+    threads: usize,
 ) {
-    restrander::_run(input_fastq, temp_fastq, restrander_config);
-    let restrander_categorised_reads = fastq::parse_categorise(temp_fastq, paf_reads, false);
+    restrander::run(input_fastq, temp_fastq, restrander_config);
+    let restrander_categorised_reads =
+        fastq::score(temp_fastq, paf_reads, restrander_header, threads).categorised;
 
     pychopper::run(
         &GenericProgramConfig {
@@ -86,16 +97,64 @@ pub fn compare(
         pychopper_config,
         paf_reads,
     );
-    let pychopper_categorised_reads = fastq::parse_categorise(temp_fastq, paf_reads, true);
-
-    let correct_venn =
-        Venn::new_correct(&restrander_categorised_reads, &pychopper_categorised_reads);
-    let incorrect_venn =
-        Venn::new_incorrect(&restrander_categorised_reads, &pychopper_categorised_reads);
-    let ambiguous_venn =
-        Venn::new_ambiguous(&restrander_categorised_reads, &pychopper_categorised_reads);
-
-    correct_venn.to_files(&format!("{}/correct", output_directory));
-    incorrect_venn.to_files(&format!("{}/incorrect", output_directory));
-    ambiguous_venn.to_files(&format!("{}/ambiguous", output_directory));
+    let pychopper_categorised_reads =
+        fastq::score(temp_fastq, paf_reads, pychopper_header, threads).categorised;
+
+    write_venns(
+        &restrander_categorised_reads,
+        &pychopper_categorised_reads,
+        output_directory,
+    );
+}
+
+/// Write where the two tools' reads overlap in each category, one
+/// directory per category under `output_directory`.
+fn write_venns(
+    restrander: &CategorisedReads,
+    pychopper: &CategorisedReads,
+    output_directory: &str,
+) {
+    Venn::new_correct(restrander, pychopper).to_files(&format!("{}/correct", output_directory));
+    Venn::new_incorrect(restrander, pychopper).to_files(&format!("{}/incorrect", output_directory));
+    Venn::new_ambiguous(restrander, pychopper).to_files(&format!("{}/ambiguous", output_directory));
+    Venn::new_untruthed(restrander, pychopper).to_files(&format!("{}/untruthed", output_directory));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn writes_every_category_into_an_empty_directory() {
+        let directory = std::env::temp_dir().join(format!(
+            "restrander-testing-rig-compare-{}",
+            std::process::id()
+        ));
+        let restrander = CategorisedReads {
+            correct: names(&["r1", "r2"]),
+            incorrect: names(&["r3"]),
+            ambiguous: names(&[]),
+            untruthed: names(&["r4"]),
+        };
+        let pychopper = CategorisedReads {
+            correct: names(&["r2", "r3"]),
+            incorrect: names(&[]),
+            ambiguous: names(&["r1"]),
+            untruthed: names(&["r4"]),
+        };
+
+        write_venns(&restrander, &pychopper, directory.to_str().unwrap());
+        let read = |path: &str| std::fs::read_to_string(directory.join(path)).unwrap();
+        assert_eq!(read("correct/intersection.csv"), "r2");
+        assert_eq!(read("correct/restrander_only.csv"), "r1");
+        assert_eq!(read("correct/pychopper_only.csv"), "r3");
+        assert_eq!(read("incorrect/restrander_only.csv"), "r3");
+        assert_eq!(read("ambiguous/pychopper_only.csv"), "r1");
+        assert_eq!(read("untruthed/intersection.csv"), "r4");
+        std::fs::remove_dir_all(directory).unwrap();
+    }
 }
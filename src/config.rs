@@ -1,12 +1,121 @@
-use crate::{fastq::AccuracyResult, json::{Config, Method}};
+use crate::{
+    accounting::OutputAccounting,
+    artefact::ArtefactResult,
+    fastq::{self, AccuracyResult},
+    flags::Flags,
+    header::HeaderParser,
+    input::InputReads,
+    integrity::SequenceIntegrity,
+    metrics::{ConfusionMatrix, Interval},
+    paf::PafReads,
+    primer::PrimerSearch,
+    reference::{self, ReferenceResult},
+    strata::{self, StratifiedResult},
+};
 use core::panic;
-use std::fmt;
+use std::{fmt, str::FromStr};
 
 #[derive(Debug, Clone)]
 pub struct ProgramResult {
     pub config: ProgramConfig,
     pub duration: u64,
     pub accuracy: AccuracyResult,
+    /// Accuracy over each output segment, for tools that split reads.
+    pub segment_accuracy: AccuracyResult,
+    pub split_reads: u64,
+    /// Not under `--low-memory`, which has no names to account with.
+    pub accounting: Option<OutputAccounting>,
+    /// Only if the sequences are being checked.
+    pub integrity: Option<SequenceIntegrity>,
+    pub artefacts: ArtefactResult,
+    pub strata: Vec<StratifiedResult>,
+    /// Per-reference and per-gene results, only if they're being saved.
+    pub references: Vec<ReferenceResult>,
+    pub confusion: ConfusionMatrix,
+    /// Bootstrap intervals for each of `ClassificationMetrics::NAMES`, if asked for.
+    pub metric_intervals: Option<Vec<Interval>>,
+}
+
+impl ProgramResult {
+    /// Score a finished run's output, check its accounting and sequences, and
+    /// break it down by the configured strata.
+    pub fn new(
+        config: ProgramConfig,
+        duration: u64,
+        paf_reads: &PafReads,
+        input_reads: &InputReads,
+        header_parser: &dyn HeaderParser,
+        scoring_config: &ScoringConfig,
+    ) -> ProgramResult {
+        let scored = fastq::score_with(
+            &config.generic.output,
+            paf_reads,
+            header_parser,
+            scoring_config,
+        );
+        let accounting = (!scoring_config.low_memory).then(|| {
+            OutputAccounting::new(
+                input_reads,
+                &config.generic.output,
+                paf_reads,
+                header_parser,
+            )
+        });
+        let integrity = scoring_config.check_sequences.then(|| {
+            SequenceIntegrity::new(
+                &config.generic.input,
+                input_reads,
+                &config.generic.output,
+                header_parser,
+            )
+        });
+        let strata = strata::stratify(
+            Some(input_reads),
+            paf_reads,
+            &scored.categorised,
+            scoring_config,
+        );
+        let references = match scoring_config.reference_tsv {
+            Some(_) => {
+                reference::aggregate_references(paf_reads, &scored.categorised, scoring_config)
+            }
+            None => vec![],
+        };
+
+        if let Some(accounting) = accounting.as_ref().filter(|a| !a.balanced()) {
+            let message = format!(
+                "Output accounting for {} doesn't balance:\n{}",
+                config.specific, accounting
+            );
+            if scoring_config.strict_accounting {
+                panic!("{}", message);
+            }
+            eprintln!("{}", message);
+        }
+        if let Some(integrity) = integrity.as_ref().filter(|i| i.mismatches() > 0) {
+            eprintln!(
+                "Output sequences for {} don't match their strand labels:\n{}",
+                config.specific, integrity
+            );
+        }
+
+        ProgramResult {
+            config,
+            duration,
+            accuracy: scored.exact.accuracy(scoring_config),
+            segment_accuracy: scored.segments.accuracy(scoring_config),
+            split_reads: scored.split_reads,
+            accounting,
+            integrity,
+            artefacts: scored.artefacts,
+            strata,
+            references,
+            metric_intervals: scoring_config
+                .bootstrap
+                .map(|bootstrap| scored.confusion.bootstrap(&bootstrap)),
+            confusion: scored.confusion,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -36,7 +145,8 @@ impl fmt::Display for ProgramConfig {
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum SpecificProgramConfig {
     Restrander(RestranderConfig),
-    Pychopper(PychopperConfig)
+    Pychopper(PychopperConfig),
+    Nanoprep,
 }
 
 impl fmt::Display for SpecificProgramConfig {
@@ -48,6 +158,7 @@ impl fmt::Display for SpecificProgramConfig {
             SpecificProgramConfig::Pychopper(config) => {
                 write!(f, "Pychopper({:?})", config.backend)
             }
+            SpecificProgramConfig::Nanoprep => write!(f, "Nanoprep"),
         }
     }
 }
@@ -55,7 +166,7 @@ impl fmt::Display for SpecificProgramConfig {
 #[derive(Debug, Clone, PartialEq, PartialOrd, Hash)]
 pub struct PychopperConfig {
     pub backend: PychopperBackend,
-    pub protocol: Protocol
+    pub protocol: Protocol,
 }
 
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
@@ -63,6 +174,7 @@ pub struct RestranderConfig {
     pub config_filename: String,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum PipelineStep {
     Poly(u64, u64),
@@ -77,10 +189,36 @@ pub enum Protocol {
 
 impl Protocol {
     pub fn new(string: &str) -> Protocol {
+        string
+            .parse()
+            .unwrap_or_else(|e| panic!("Bad protocol string argument: {}", e))
+    }
+
+    /// The template switching oligo.
+    pub fn tso(&self) -> &'static str {
+        match self {
+            Protocol::PCB109 => "TTTCTGTTGGTGCTGATATTGCTGGG",
+            Protocol::PCB111 => "TTTCTGTTGGTGCTGATATTGCTTT",
+        }
+    }
+
+    /// The reverse transcription primer.
+    pub fn rtp(&self) -> &'static str {
+        match self {
+            Protocol::PCB109 => "ACTTGCCTGTCGCTCTATCTTCTTTTTTTTTT",
+            Protocol::PCB111 => "CTTGCCTGTCGCTCTATCTTCAGAGGAG",
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
         match string {
-            "PCB109" => Protocol::PCB109,
-            "PCB111" => Protocol::PCB111,
-            _ => panic!("Bad protocol string argument!")
+            "PCB109" => Ok(Protocol::PCB109),
+            "PCB111" => Ok(Protocol::PCB111),
+            _ => Err(format!("unknown protocol {}", string)),
         }
     }
 }
@@ -88,5 +226,385 @@ impl Protocol {
 #[derive(Debug, Clone, PartialEq, Hash, PartialOrd)]
 pub enum PychopperBackend {
     Edlib,
-    MachineLearning
+    MachineLearning,
+}
+
+/// Which PAF alignments are trusted as ground truth.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TruthConfig {
+    pub min_mapq: u8,
+    pub min_identity: f64,
+    pub min_query_coverage: f64,
+    pub multimapper: MultiMapperPolicy,
+    /// GTF/GFF3 annotation for turning genome alignment strands into transcript strands.
+    pub annotation: Option<String>,
+    pub antisense: AntisensePolicy,
+    pub intergenic: IntergenicPolicy,
+    /// Take the transcript strand from minimap2's `ts:A` tag on spliced alignments.
+    pub ts_tag: bool,
+    pub missing_ts: MissingTsPolicy,
+    /// Reads whose alignments all cover less than this much of the read can be
+    /// called chimeric, if they also align to opposite strands.
+    pub chimera_max_coverage: f64,
+    /// Directory for the parsed truth set cache, only used if given.
+    pub cache: Option<String>,
+    /// Keep only a hash of each truth read's name, for `--low-memory` scoring.
+    pub low_memory: bool,
+}
+
+impl TruthConfig {
+    pub fn from_flags(flags: &Flags) -> TruthConfig {
+        let config = TruthConfig {
+            min_mapq: flags.get("min-mapq", 0),
+            min_identity: flags.get("min-identity", 0.0),
+            min_query_coverage: flags.get("min-query-coverage", 0.0),
+            multimapper: flags.get("multimapper", MultiMapperPolicy::First),
+            annotation: flags.get_opt("annotation"),
+            antisense: flags.get("antisense", AntisensePolicy::Drop),
+            intergenic: flags.get("intergenic", IntergenicPolicy::Drop),
+            ts_tag: flags.get("ts-tag", false),
+            missing_ts: flags.get("missing-ts", MissingTsPolicy::Exclude),
+            chimera_max_coverage: flags.get("chimera-max-coverage", 0.8),
+            cache: flags.get_opt("truth-cache"),
+            low_memory: flags.get("low-memory", false),
+        };
+
+        if config.ts_tag && config.annotation.is_some() {
+            panic!("--ts-tag and --annotation are two different sources of truth, pick one!")
+        }
+        if config.low_memory && config.cache.is_some() {
+            panic!("--low-memory doesn't keep read names, so can't be used with --truth-cache");
+        }
+
+        config
+    }
+}
+
+/// How to pick a truth strand for a read with several alignments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MultiMapperPolicy {
+    /// Use the first alignment in the file.
+    First,
+    /// Use the primary alignment (`tp:A:P` in PAF, FLAG in SAM/BAM).
+    Primary,
+    /// Use the alignment with the highest MAPQ, keeping the first on ties.
+    BestMapq,
+    /// Use the strand most alignments agree on, dropping the read on a tie.
+    Majority,
+    /// Drop the read if its alignments disagree on strand.
+    DropConflicting,
+}
+
+impl FromStr for MultiMapperPolicy {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "first" => Ok(MultiMapperPolicy::First),
+            "primary" => Ok(MultiMapperPolicy::Primary),
+            "best-mapq" => Ok(MultiMapperPolicy::BestMapq),
+            "majority" => Ok(MultiMapperPolicy::Majority),
+            "drop-conflicting" => Ok(MultiMapperPolicy::DropConflicting),
+            _ => Err(format!("unknown multi-mapper policy {}", string)),
+        }
+    }
+}
+
+/// What to do with a genome-aligned read overlapping genes on both strands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AntisensePolicy {
+    /// Leave the read out of the truth set.
+    Drop,
+    /// Take the strand with the most overlapping bases, dropping the read on a tie.
+    MostOverlap,
+}
+
+impl FromStr for AntisensePolicy {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "drop" => Ok(AntisensePolicy::Drop),
+            "most-overlap" => Ok(AntisensePolicy::MostOverlap),
+            _ => Err(format!("unknown antisense policy {}", string)),
+        }
+    }
+}
+
+/// What to do with a genome-aligned read overlapping no gene.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntergenicPolicy {
+    /// Leave the read out of the truth set.
+    Drop,
+    /// Fall back to the alignment strand.
+    Alignment,
+}
+
+impl FromStr for IntergenicPolicy {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "drop" => Ok(IntergenicPolicy::Drop),
+            "alignment" => Ok(IntergenicPolicy::Alignment),
+            _ => Err(format!("unknown intergenic policy {}", string)),
+        }
+    }
+}
+
+/// What to do with a read whose alignment has no usable `ts:A` tag.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MissingTsPolicy {
+    /// Leave the read out of the truth set.
+    Exclude,
+    /// Keep the read, but with an unknown (`?`) truth strand.
+    Untruthed,
+}
+
+impl FromStr for MissingTsPolicy {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "exclude" => Ok(MissingTsPolicy::Exclude),
+            "untruthed" => Ok(MissingTsPolicy::Untruthed),
+            _ => Err(format!("unknown missing ts policy {}", string)),
+        }
+    }
+}
+
+/// How tool output is scored against the truth set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoringConfig {
+    pub untruthed: UntruthedPolicy,
+    pub bootstrap: Option<Bootstrap>,
+    /// Fail the run if a tool's output has duplicate, extra or unreadable records.
+    pub strict_accounting: bool,
+    /// Check that output records hold the input's sequences, turned round
+    /// for `-` reads. Off by default, as it fingerprints every input read.
+    pub check_sequences: bool,
+    /// Which read properties to break the accuracy down by.
+    pub strata: Strata,
+    pub length_bins: Edges,
+    pub length_source: LengthSource,
+    pub quality_bins: Edges,
+    /// The protocol whose primers to look for. Filled in from the command line
+    /// protocol for runs that have one.
+    pub primer_protocol: Option<Protocol>,
+    pub primer_search: PrimerSearch,
+    /// Where to write the per-reference breakdown, if anywhere.
+    pub reference_tsv: Option<String>,
+    /// Transcript to gene table, to add per-gene rows to the breakdown.
+    pub gene_table: Option<String>,
+    /// Score with read names interned as hashes, for runs too big to hold
+    /// every name in memory. There are then no names to break results down
+    /// by, or to account for the output and check its sequences with.
+    pub low_memory: bool,
+    /// Threads to parse the output records with, and as many again to
+    /// classify them. Only scoring is threaded: accounting, sequence checks,
+    /// strata and the primer search are single passes, and under
+    /// `--low-memory` only the parsing is.
+    pub threads: usize,
+}
+
+impl ScoringConfig {
+    pub fn from_flags(flags: &Flags) -> ScoringConfig {
+        let replicates: Option<u32> = flags.get_opt("bootstrap");
+        let seed = flags.get("bootstrap-seed", 1);
+
+        let scoring_config = ScoringConfig {
+            untruthed: flags.get("untruthed", UntruthedPolicy::Exclude),
+            bootstrap: replicates.map(|replicates| Bootstrap { replicates, seed }),
+            strict_accounting: flags.get("strict-accounting", false),
+            check_sequences: flags.get("check-sequences", false),
+            strata: flags.get("stratify", Strata(vec![])),
+            // log-spaced, doubling from 100bp
+            length_bins: flags.get(
+                "length-bins",
+                Edges((0..9).map(|i| 100.0 * 2_f64.powi(i)).collect()),
+            ),
+            length_source: flags.get("length-source", LengthSource::Fastq),
+            quality_bins: flags.get("quality-bins", Edges(vec![7.0, 10.0, 12.0, 15.0, 20.0])),
+            primer_protocol: flags.get_opt("primer-protocol"),
+            primer_search: PrimerSearch {
+                max_edit_distance: flags.get("primer-edit-distance", 6),
+                window: flags.get("primer-window", 200),
+            },
+            reference_tsv: flags.get_opt("reference-tsv"),
+            gene_table: flags.get_opt("gene-table"),
+            low_memory: flags.get("low-memory", false),
+            threads: flags.get("threads", 1),
+        };
+
+        if scoring_config.low_memory
+            && (!scoring_config.strata.0.is_empty()
+                || scoring_config.reference_tsv.is_some()
+                || scoring_config.check_sequences
+                || scoring_config.strict_accounting)
+        {
+            panic!("--low-memory doesn't keep read names, so can't be used with --stratify, --reference-tsv, --check-sequences or --strict-accounting");
+        }
+
+        scoring_config
+    }
+}
+
+/// Resample the scored reads this many times to put intervals on the
+/// derived metrics. The same seed always gives the same intervals.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bootstrap {
+    pub replicates: u32,
+    pub seed: u64,
+}
+
+/// Whether output reads with no truth strand count towards the denominator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UntruthedPolicy {
+    Exclude,
+    Include,
+}
+
+impl FromStr for UntruthedPolicy {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "exclude" => Ok(UntruthedPolicy::Exclude),
+            "include" => Ok(UntruthedPolicy::Include),
+            _ => Err(format!("unknown untruthed policy {}", string)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tool {
+    Restrander,
+    Pychopper,
+    Nanoprep,
+}
+
+impl FromStr for Tool {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "restrander" => Ok(Tool::Restrander),
+            "pychopper" => Ok(Tool::Pychopper),
+            "nanoprep" => Ok(Tool::Nanoprep),
+            _ => Err(format!("unknown tool {}", string)),
+        }
+    }
+}
+
+/// Which tools to run, given as a comma-separated list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tools(pub Vec<Tool>);
+
+impl Tools {
+    pub fn contains(&self, tool: Tool) -> bool {
+        self.0.contains(&tool)
+    }
+}
+
+impl Default for Tools {
+    fn default() -> Self {
+        Tools(vec![Tool::Restrander, Tool::Pychopper])
+    }
+}
+
+impl FromStr for Tools {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        string
+            .split(',')
+            .map(Tool::from_str)
+            .collect::<Result<_, _>>()
+            .map(Tools)
+    }
+}
+
+/// A read property to stratify the results by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stratum {
+    Length,
+    /// Mean Phred quality of the input read.
+    Quality,
+    /// Which of the protocol's primers were found in the input read.
+    Primers,
+}
+
+impl FromStr for Stratum {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "length" => Ok(Stratum::Length),
+            "quality" => Ok(Stratum::Quality),
+            "primers" => Ok(Stratum::Primers),
+            _ => Err(format!("unknown stratum {}", string)),
+        }
+    }
+}
+
+/// Which read properties to stratify by, given as a comma-separated list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Strata(pub Vec<Stratum>);
+
+impl Strata {
+    pub fn contains(&self, stratum: Stratum) -> bool {
+        self.0.contains(&stratum)
+    }
+}
+
+impl FromStr for Strata {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        string
+            .split(',')
+            .map(Stratum::from_str)
+            .collect::<Result<_, _>>()
+            .map(Strata)
+    }
+}
+
+/// Bin edges, given as a comma-separated list in increasing order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edges(pub Vec<f64>);
+
+impl FromStr for Edges {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let edges = string
+            .split(',')
+            .map(|edge| edge.parse().map_err(|_| format!("bad bin edge {}", edge)))
+            .collect::<Result<Vec<f64>, _>>()?;
+        if edges.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(format!("bin edges {} aren't increasing", string));
+        }
+        Ok(Edges(edges))
+    }
+}
+
+/// Where read lengths come from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LengthSource {
+    /// The length of each input read.
+    Fastq,
+    /// The query length of each read's truth alignment.
+    Paf,
+}
+
+impl FromStr for LengthSource {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "fastq" => Ok(LengthSource::Fastq),
+            "paf" => Ok(LengthSource::Paf),
+            _ => Err(format!("unknown length source {}", string)),
+        }
+    }
 }
@@ -3,5 +3,7 @@
 // pub const CONDA_PATH: &str = "/stornext/Home/data/allstaff/s/schuster.j/jakob/tools/conda";
 
 pub const RESTRANDER_PATH: &str = "../restrander/restrander";
+#[allow(dead_code)]
 pub const OUTPUT_FILENAME: &str = "/home/jakob/Documents/restrander-paper/error-rate-testing/temp.fq";
-pub const CONDA_PATH: &str = "/home/jakob/miniconda3/bin/conda";
\ No newline at end of file
+pub const CONDA_PATH: &str = "/home/jakob/miniconda3/bin/conda";
+pub const NANOPREP_PATH: &str = "nanoprep";
\ No newline at end of file
@@ -1,10 +1,20 @@
-use seq_io::fastq::{Reader, Record};
+use itertools::Itertools;
+use seq_io::fastq::{Record, RefRecord};
+use serde_json::{json, Value};
 use std::{
     collections::{HashMap, HashSet},
-    str,
+    hash::{DefaultHasher, Hash, Hasher},
+    mem, str,
+    sync::mpsc,
+    thread,
 };
 
-use crate::paf::{PafRead, PafReads};
+use crate::artefact::{Artefact, ArtefactResult};
+use crate::config::{ScoringConfig, UntruthedPolicy};
+use crate::header::{HeaderParser, ParsedHeader};
+use crate::metrics::{text_value, ConfusionMatrix, Interval, Prediction, TruthLabel};
+use crate::paf::PafReads;
+use crate::reader;
 use std::fmt;
 
 #[derive(Debug, Clone)]
@@ -13,14 +23,44 @@ pub struct AccuracyResult {
     pub correct: f64,
     pub incorrect: f64,
     pub ambiguous: f64,
+    /// Output reads with no known truth strand. Only part of the denominator
+    /// if they're included by the untruthed policy.
+    pub untruthed: f64,
+    /// Wilson score intervals for each of the above, in the same units.
+    pub intervals: AccuracyIntervals,
+}
+
+#[derive(Debug, Clone)]
+pub struct AccuracyIntervals {
+    pub correct: Interval,
+    pub incorrect: Interval,
+    pub ambiguous: Interval,
+    /// Undefined when untruthed reads are excluded from the denominator,
+    /// since they're then not a proportion of it.
+    pub untruthed: Interval,
+}
+
+impl AccuracyIntervals {
+    pub const NAMES: [&'static str; 4] = ["correct", "incorrect", "ambiguous", "untruthed"];
+
+    pub fn values(&self) -> [Interval; 4] {
+        [self.correct, self.incorrect, self.ambiguous, self.untruthed]
+    }
 }
 
 impl AccuracyResult {
-    fn new(result: &AccuracyResultExact, total: i32) -> AccuracyResult {
+    fn new(result: &AccuracyResultExact, total: u64) -> AccuracyResult {
         AccuracyResult {
             correct: result.correct as f64 / total as f64,
             incorrect: result.incorrect as f64 / total as f64,
             ambiguous: result.ambiguous as f64 / total as f64,
+            untruthed: result.untruthed as f64 / total as f64,
+            intervals: AccuracyIntervals {
+                correct: Interval::wilson(result.correct, total),
+                incorrect: Interval::wilson(result.incorrect, total),
+                ambiguous: Interval::wilson(result.ambiguous, total),
+                untruthed: Interval::wilson(result.untruthed, total),
+            },
         }
     }
 
@@ -29,81 +69,139 @@ impl AccuracyResult {
             correct: self.correct * 100_f64,
             incorrect: self.incorrect * 100_f64,
             ambiguous: self.ambiguous * 100_f64,
+            untruthed: self.untruthed * 100_f64,
+            intervals: AccuracyIntervals {
+                correct: self.intervals.correct.scale(100_f64),
+                incorrect: self.intervals.incorrect.scale(100_f64),
+                ambiguous: self.intervals.ambiguous.scale(100_f64),
+                untruthed: self.intervals.untruthed.scale(100_f64),
+            },
         }
     }
 }
 
+impl AccuracyResult {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "correct_percent": self.correct,
+            "incorrect_percent": self.incorrect,
+            "ambiguous_percent": self.ambiguous,
+            "untruthed_percent": self.untruthed,
+            "intervals": {
+                "correct_percent": self.intervals.correct.to_json(),
+                "incorrect_percent": self.intervals.incorrect.to_json(),
+                "ambiguous_percent": self.intervals.ambiguous.to_json(),
+                "untruthed_percent": self.intervals.untruthed.to_json(),
+            },
+        })
+    }
+}
+
 impl fmt::Display for AccuracyResult {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "correct {}\nincorrect {}\nambiguous {}",
-            self.correct, self.incorrect, self.ambiguous
+            "correct {} {}\nincorrect {} {}\nambiguous {} {}\nuntruthed {} {}",
+            text_value(self.correct),
+            self.intervals.correct,
+            text_value(self.incorrect),
+            self.intervals.incorrect,
+            text_value(self.ambiguous),
+            self.intervals.ambiguous,
+            text_value(self.untruthed),
+            self.intervals.untruthed
         )
     }
 }
 
+#[derive(Debug, PartialEq)]
 pub struct AccuracyResultExact {
     pub correct: u64,
     pub incorrect: u64,
+    /// Labelled `?` (or anything other than `+`/`-`), or missing from the output.
     pub ambiguous: u64,
+    pub untruthed: u64,
+    /// Truth reads that never appeared in the output, counted within `ambiguous`.
+    pub missing: u64,
+    /// Repeat output records for a read already seen, which aren't scored.
+    pub duplicates: u64,
 }
 
-impl AccuracyResultExact {
-    fn total(&self) -> u64 {
-        self.correct + self.incorrect + self.ambiguous
+/// Where a single scored read (or segment) lands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Category {
+    Correct,
+    Incorrect,
+    Ambiguous,
+    Untruthed,
+}
+
+impl Category {
+    fn new(truth: Option<char>, label: char) -> Category {
+        match truth {
+            None | Some('?') => Category::Untruthed,
+            Some(_) if label != '+' && label != '-' => Category::Ambiguous,
+            Some(strand) if label == strand => Category::Correct,
+            Some(_) => Category::Incorrect,
+        }
     }
 }
 
-pub fn parse(filename: &str, paf_reads: &PafReads, is_pychopper: bool) -> AccuracyResult {
-    let size = paf_reads.size;
-    let mut result_exact: AccuracyResultExact = AccuracyResultExact {
-        correct: 0,
-        incorrect: 0,
-        ambiguous: 0,
-    };
+impl AccuracyResultExact {
+    fn new() -> AccuracyResultExact {
+        AccuracyResultExact {
+            correct: 0,
+            incorrect: 0,
+            ambiguous: 0,
+            untruthed: 0,
+            missing: 0,
+            duplicates: 0,
+        }
+    }
 
-    let mut reader = Reader::from_path(filename).unwrap();
+    fn add(&mut self, category: Category) {
+        match category {
+            Category::Correct => self.correct += 1,
+            Category::Incorrect => self.incorrect += 1,
+            Category::Ambiguous => self.ambiguous += 1,
+            Category::Untruthed => self.untruthed += 1,
+        }
+    }
+    fn merge(&mut self, other: AccuracyResultExact) {
+        self.correct += other.correct;
+        self.incorrect += other.incorrect;
+        self.ambiguous += other.ambiguous;
+        self.untruthed += other.untruthed;
+        self.missing += other.missing;
+        self.duplicates += other.duplicates;
+    }
 
-    // let size = paf_reads.clone().size();
+    fn total(&self) -> u64 {
+        self.correct + self.incorrect + self.ambiguous
+    }
 
-    while let Some(record) = reader.next() {
-        let record = record.expect("Error reading record");
+    /// Every scored read, whatever its category.
+    pub fn reads(&self) -> u64 {
+        self.total() + self.untruthed
+    }
 
-        // get the name differently depending on whether this is pychopper
-        let name = if is_pychopper {
-            record.id().unwrap().split('|').collect::<Vec<_>>()[1]
-        } else {
-            record.id().unwrap().split('|').collect::<Vec<_>>()[0]
+    /// Turn the counts into percentages of the denominator, which is every read with
+    /// a known truth strand, plus the untruthed reads if the policy says so.
+    pub fn accuracy(&self, scoring_config: &ScoringConfig) -> AccuracyResult {
+        let total = match scoring_config.untruthed {
+            UntruthedPolicy::Exclude => self.total(),
+            UntruthedPolicy::Include => self.total() + self.untruthed,
         };
-
-        // skip non-matching records
-        let strand = paf_reads
-            .map
-            .get(&name.to_string())
-            .expect("Failed to read!")
-            .to_owned();
-        let current = *record.head().last().unwrap() as char;
-
-        if current == '?' {
-            result_exact.ambiguous += 1;
-        } else if current == strand {
-            result_exact.correct += 1;
-        } else {
-            result_exact.incorrect += 1;
-        }
+        AccuracyResult::new(self, total).to_percent()
     }
-
-    result_exact.ambiguous = size as u64 - (result_exact.correct + result_exact.incorrect);
-
-    AccuracyResult::new(&result_exact, size).to_percent()
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CategorisedReads {
     pub correct: HashSet<String>,
     pub incorrect: HashSet<String>,
     pub ambiguous: HashSet<String>,
+    pub untruthed: HashSet<String>,
 }
 
 impl CategorisedReads {
@@ -112,104 +210,592 @@ impl CategorisedReads {
             correct: HashSet::new(),
             incorrect: HashSet::new(),
             ambiguous: HashSet::new(),
+            untruthed: HashSet::new(),
         }
     }
+
+    fn insert(&mut self, category: Category, name: String) {
+        match category {
+            Category::Correct => self.correct.insert(name),
+            Category::Incorrect => self.incorrect.insert(name),
+            Category::Ambiguous => self.ambiguous.insert(name),
+            Category::Untruthed => self.untruthed.insert(name),
+        };
+    }
+
+    fn merge(&mut self, other: CategorisedReads) {
+        self.correct.extend(other.correct);
+        self.incorrect.extend(other.incorrect);
+        self.ambiguous.extend(other.ambiguous);
+        self.untruthed.extend(other.untruthed);
+    }
+
+    /// Split the counts into `bins` bins, skipping reads that aren't in any bin.
+    pub fn count_by(
+        &self,
+        bins: usize,
+        bin_of: impl Fn(&str) -> Option<usize>,
+    ) -> Vec<AccuracyResultExact> {
+        let mut counts = (0..bins).map(|_| AccuracyResultExact::new()).collect_vec();
+        for (names, category) in [
+            (&self.correct, Category::Correct),
+            (&self.incorrect, Category::Incorrect),
+            (&self.ambiguous, Category::Ambiguous),
+            (&self.untruthed, Category::Untruthed),
+        ] {
+            for name in names {
+                if let Some(bin) = bin_of(name) {
+                    counts[bin].add(category);
+                }
+            }
+        }
+        counts
+    }
 }
 
-pub fn parse_categorise(
+/// The exact counts, confusion matrix and per-read categories from one
+/// scoring pass, so they always agree.
+pub struct ScoredReads {
+    /// One verdict per read, however many segments it was split into.
+    pub exact: AccuracyResultExact,
+    /// One verdict per output segment, against the truth for its read.
+    pub segments: AccuracyResultExact,
+    /// Reads that came out as more than one segment.
+    pub split_reads: u64,
+    pub confusion: ConfusionMatrix,
+    /// Artefact labels against the chimeras in the truth set.
+    pub artefacts: ArtefactResult,
+    pub categorised: CategorisedReads,
+}
+
+impl ScoredReads {
+    fn new(header_parser: &dyn HeaderParser) -> ScoredReads {
+        ScoredReads {
+            exact: AccuracyResultExact::new(),
+            segments: AccuracyResultExact::new(),
+            split_reads: 0,
+            confusion: ConfusionMatrix::default(),
+            artefacts: ArtefactResult::new(header_parser.reports_artefacts()),
+            categorised: CategorisedReads::new(),
+        }
+    }
+
+    /// Add in the counts and reads from another set of scored reads.
+    fn merge(&mut self, other: ScoredReads) {
+        self.exact.merge(other.exact);
+        self.segments.merge(other.segments);
+        self.split_reads += other.split_reads;
+        self.confusion.merge(&other.confusion);
+        self.artefacts.merge(&other.artefacts);
+        self.categorised.merge(other.categorised);
+    }
+
+    /// An output record whose header couldn't be parsed.
+    fn add_unparsed(&mut self) {
+        self.exact.untruthed += 1;
+        self.segments.untruthed += 1;
+        self.confusion
+            .add(TruthLabel::Untruthed, Prediction::Ambiguous);
+    }
+
+    /// A read that made it into the output, given the labels on each of its
+    /// segments. The segments themselves are scored as they're read.
+    fn add_read(
+        &mut self,
+        truth: Option<char>,
+        chimeric: bool,
+        labels: &[char],
+        artefact: Option<Artefact>,
+    ) -> Category {
+        let current = combine_labels(labels);
+
+        if labels.len() > 1 {
+            self.split_reads += 1;
+        }
+        if truth.is_some() {
+            self.artefacts.add(chimeric, artefact);
+        }
+
+        self.confusion.add(
+            TruthLabel::from_strand(truth),
+            Prediction::from_strand(current),
+        );
+
+        let category = Category::new(truth, current);
+        self.exact.add(category);
+        category
+    }
+
+    /// A truth read the tool didn't output at all. Returns whether it counts
+    /// as ambiguous, which it does unless its truth strand is unknown.
+    fn add_missing(&mut self, strand: char) -> bool {
+        self.confusion
+            .add(TruthLabel::from_strand(Some(strand)), Prediction::Missing);
+        if strand == '?' {
+            return false;
+        }
+        for exact in [&mut self.exact, &mut self.segments] {
+            exact.ambiguous += 1;
+            exact.missing += 1;
+        }
+        true
+    }
+}
+
+/// The strand for a whole read, from the labels on its segments. The read is
+/// only called if every segment that was called agrees.
+fn combine_labels(labels: &[char]) -> char {
+    let mut called = labels.iter().filter(|label| matches!(label, '+' | '-'));
+    match called.next() {
+        Some(first) if called.all(|label| label == first) => *first,
+        _ => '?',
+    }
+}
+
+/// Score a tool's output against the truth set.
+///
+/// - Every truth read with a known strand lands in exactly one of correct,
+///   incorrect or ambiguous. Those missing from the output are ambiguous.
+/// - Output reads labelled `?`, or with any label other than `+`/`-`, are ambiguous.
+/// - Output reads with no truth entry, or a `?` truth strand, are untruthed.
+/// - A read split into several segments is scored once at the read level,
+///   using `combine_labels`, and once per segment at the segment level.
+/// - Only the first output record for each segment is scored, later ones are
+///   counted as duplicates.
+/// - Headers the parser can't make sense of are counted as untruthed too,
+///   since they can't be matched to the truth, but have no name to list in
+///   `categorised`.
+///
+/// With more than one thread, the records are parsed in parallel and then
+/// classified in shards by read name, so every record for a read lands in
+/// the same shard, in file order. The shards' counts are summed at the end,
+/// so the results are the same whatever the thread count.
+pub fn score(
     filename: &str,
     paf_reads: &PafReads,
-    is_pychopper: bool,
-) -> CategorisedReads {
-    let mut reader = Reader::from_path(filename).unwrap();
-
-    let mut fastq_reads: HashMap<String, char> = HashMap::new();
-    while let Some(record) = reader.next() {
-        let record = record.expect("Error reading record");
-        let name = if is_pychopper {
-            record.id().unwrap().split('|').collect::<Vec<_>>()[1]
-        } else {
-            // restrander it goes to the 0 spot
-            record.id().unwrap().split('|').collect::<Vec<_>>()[0]
-        };
+    header_parser: &dyn HeaderParser,
+    threads: usize,
+) -> ScoredReads {
+    let shard_count = threads.max(1);
+    let parse = |record: RefRecord| {
+        let header = str::from_utf8(record.head()).expect("Header is not valid UTF-8");
+        match header_parser.parse(header) {
+            Some(parsed) => (
+                shard_of(parsed.name, shard_count),
+                Some(OwnedHeader::new(parsed)),
+            ),
+            None => (shard_of(header, shard_count), None),
+        }
+    };
 
-        // skip non-matching records
-        let current = *record.head().last().unwrap();
-
-        fastq_reads.insert(name.to_string(), current as char);
-    }
-
-    let mut reads = CategorisedReads::new();
-    for paf_key in paf_reads.map.keys() {
-        // skip ambiguous entries
-        if !fastq_reads.contains_key(paf_key) {
-            reads.ambiguous.insert(paf_key.clone());
-            continue;
-        }
-
-        // categorise!
-        if fastq_reads.contains_key(paf_key) {
-            if *paf_reads
-                .map
-                .get(&paf_key.clone())
-                .expect("Paf map didn't have key")
-                == *fastq_reads
-                    .get(&paf_key.clone())
-                    .expect("Fastq map didn't have key")
-            {
-                reads.correct.insert(paf_key.clone());
-            } else {
-                reads.incorrect.insert(paf_key.clone());
+    let shards = if shard_count == 1 {
+        let mut shard = Shard::new(paf_reads, header_parser);
+        reader::map_fastq(filename, 1, parse, |(_, parsed)| shard.add(parsed));
+        shard.score_reads();
+        vec![shard]
+    } else {
+        thread::scope(|scope| {
+            let (senders, workers): (Vec<_>, Vec<_>) = (0..shard_count)
+                .map(|_| {
+                    let mut shard = Shard::new(paf_reads, header_parser);
+                    let (send, recv) = mpsc::sync_channel::<Vec<Parsed>>(4);
+                    let worker = scope.spawn(move || {
+                        for batch in recv {
+                            batch.into_iter().for_each(|parsed| shard.add(parsed));
+                        }
+                        shard.score_reads();
+                        shard
+                    });
+                    (send, worker)
+                })
+                .unzip();
+
+            // hand the records over in batches, to keep the channels quiet
+            let mut batches = senders.iter().map(|_| vec![]).collect_vec();
+            reader::map_fastq(filename, threads, parse, |(shard, parsed)| {
+                batches[shard].push(parsed);
+                if batches[shard].len() == SHARD_BATCH {
+                    senders[shard]
+                        .send(mem::take(&mut batches[shard]))
+                        .expect("Scoring thread stopped");
+                }
+            });
+            for (sender, batch) in senders.into_iter().zip(batches) {
+                sender.send(batch).expect("Scoring thread stopped");
             }
+
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("Scoring thread panicked"))
+                .collect_vec()
+        })
+    };
+
+    let mut scored = ScoredReads::new(header_parser);
+
+    // truth reads the tool didn't output at all
+    for (name, strand) in paf_reads.map.iter() {
+        let shard = &shards[shard_of(name, shard_count)];
+        if !shard.labels.contains_key(name) && scored.add_missing(*strand) {
+            scored.categorised.ambiguous.insert(name.clone());
         }
     }
 
-    reads
+    for shard in shards {
+        scored.merge(shard.scored);
+    }
+    scored
 }
 
-pub fn parse_nanoprep(filename: &str, paf_reads: &[PafRead]) {
-    let mut correct = 0;
-    let mut incorrect = 0;
-    let mut ambiguous = 0;
+/// Records handed to each scoring thread at a time.
+const SHARD_BATCH: usize = 1024;
+
+/// A parsed output record, or None if its header couldn't be parsed.
+type Parsed = Option<OwnedHeader>;
+
+/// Which shard a read (or unparsed header) is classified in.
+fn shard_of(name: &str, shards: usize) -> usize {
+    match shards {
+        1 => 0,
+        _ => (name_hash(name) % shards as u64) as usize,
+    }
+}
+
+/// The reads one scoring thread is responsible for.
+struct Shard<'a> {
+    paf_reads: &'a PafReads,
+    scored: ScoredReads,
+    seen: HashSet<(String, Option<String>)>,
+    /// The labels on every segment of each read, and any artefact on one of them.
+    labels: HashMap<String, Vec<char>>,
+    artefacts: HashMap<String, Artefact>,
+}
+
+impl<'a> Shard<'a> {
+    fn new(paf_reads: &'a PafReads, header_parser: &dyn HeaderParser) -> Shard<'a> {
+        Shard {
+            paf_reads,
+            scored: ScoredReads::new(header_parser),
+            seen: HashSet::new(),
+            labels: HashMap::new(),
+            artefacts: HashMap::new(),
+        }
+    }
+
+    /// Score one output record's segment, in file order.
+    fn add(&mut self, parsed: Parsed) {
+        let OwnedHeader {
+            name,
+            segment,
+            strand: current,
+            artefact,
+        } = match parsed {
+            Some(parsed) => parsed,
+            None => {
+                self.scored.add_unparsed();
+                return;
+            }
+        };
 
-    let mut reader = Reader::from_path(filename).unwrap();
+        if !self.seen.insert((name.clone(), segment)) {
+            self.scored.exact.duplicates += 1;
+            return;
+        }
 
-    let mut i: usize = 0;
-    while let Some(record) = reader.next() {
-        let record = record.expect("Error reading record");
+        self.scored.segments.add(Category::new(
+            self.paf_reads.map.get(&name).copied(),
+            current,
+        ));
+        self.labels.entry(name.clone()).or_default().push(current);
+        if let Some(artefact) = artefact {
+            self.artefacts.insert(name, artefact);
+        }
+    }
 
-        let name = record.id().unwrap();
-        println!("{} vs {}", name, paf_reads[i].name);
-        while name != paf_reads[i].name {
-            println!(
-                "{} vs {} not the same so incrementing",
-                name, paf_reads[i].name
+    /// Now every segment is in, score the reads as a whole.
+    fn score_reads(&mut self) {
+        for (name, labels) in self.labels.iter() {
+            let category = self.scored.add_read(
+                self.paf_reads.map.get(name).copied(),
+                self.paf_reads.chimeric.contains(name),
+                labels,
+                self.artefacts.get(name).copied(),
             );
-            i += 1;
+            self.scored.categorised.insert(category, name.clone());
         }
+    }
+}
+
+/// Score with whichever of `score` and `score_low_memory` the config asks for.
+pub fn score_with(
+    filename: &str,
+    paf_reads: &PafReads,
+    header_parser: &dyn HeaderParser,
+    scoring_config: &ScoringConfig,
+) -> ScoredReads {
+    if scoring_config.low_memory {
+        score_low_memory(filename, paf_reads, header_parser, scoring_config.threads)
+    } else {
+        score(filename, paf_reads, header_parser, scoring_config.threads)
+    }
+}
+
+/// A read name (or segment) interned as a 64-bit hash.
+pub fn name_hash<T: Hash + ?Sized>(name: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// One parsed output record, without its name.
+struct OutputEntry {
+    name: u64,
+    segment: u64,
+    /// Where the record was in the file, so duplicates can be told apart
+    /// from the first record for a segment.
+    index: u64,
+    label: char,
+    artefact: Option<Artefact>,
+}
 
-        let record_string = match str::from_utf8(record.head()) {
-            Ok(v) => v,
-            Err(e) => panic!("Invalid UTF-8 sequence: {}", e),
+/// A `ParsedHeader` that owns its strings, so it can be passed between threads.
+struct OwnedHeader {
+    name: String,
+    segment: Option<String>,
+    strand: char,
+    artefact: Option<Artefact>,
+}
+
+impl OwnedHeader {
+    fn new(parsed: ParsedHeader) -> OwnedHeader {
+        OwnedHeader {
+            name: parsed.name.to_string(),
+            segment: parsed.segment.map(str::to_string),
+            strand: parsed.strand,
+            artefact: parsed.artefact,
+        }
+    }
+}
+
+/// The same as `score`, but with the read names interned as 64-bit hashes.
+/// The output records are sorted by hash and merge-joined with the truth
+/// reads, which `paf::hashed_from_records` has already sorted, so nothing is
+/// held per read but a few numbers.
+///
+/// The per-read categories aren't kept, since there are no names to keep, so
+/// `categorised` is left empty.
+pub fn score_low_memory(
+    filename: &str,
+    paf_reads: &PafReads,
+    header_parser: &dyn HeaderParser,
+    threads: usize,
+) -> ScoredReads {
+    let mut scored = ScoredReads::new(header_parser);
+
+    let parse = |record: RefRecord| {
+        let header = str::from_utf8(record.head()).expect("Header is not valid UTF-8");
+        header_parser.parse(header).map(|parsed| OutputEntry {
+            name: name_hash(parsed.name),
+            segment: name_hash(&parsed.segment),
+            // filled in once it's back in file order
+            index: 0,
+            label: parsed.strand,
+            artefact: parsed.artefact,
+        })
+    };
+
+    let mut entries: Vec<OutputEntry> = vec![];
+    reader::map_fastq(filename, threads, parse, |entry| match entry {
+        Some(entry) => entries.push(OutputEntry {
+            index: entries.len() as u64,
+            ..entry
+        }),
+        None => scored.add_unparsed(),
+    });
+    entries.sort_unstable_by_key(|entry| (entry.name, entry.segment, entry.index));
+
+    let mut truth = paf_reads.hashed.iter().peekable();
+
+    for group in entries.chunk_by(|a, b| a.name == b.name) {
+        let name = group[0].name;
+
+        // truth reads that sort before this one never made it to the output
+        while let Some(read) = truth.next_if(|read| read.name < name) {
+            scored.add_missing(read.strand);
+        }
+        let (strand, chimeric) = match truth.next_if(|read| read.name == name) {
+            Some(read) => (Some(read.strand), read.chimeric),
+            None => (None, false),
         };
 
-        // extract the strand from the tag line
-        let strand = record_string.split(' ').nth(7).unwrap().as_bytes()[7];
+        // the first record for each segment, in file order
+        let mut labels = vec![];
+        let mut artefact: Option<(u64, Artefact)> = None;
+        for records in group.chunk_by(|a, b| a.segment == b.segment) {
+            let first = &records[0];
+            scored.exact.duplicates += records.len() as u64 - 1;
+
+            scored.segments.add(Category::new(strand, first.label));
+            labels.push((first.index, first.label));
+            if let Some(found) = first.artefact {
+                // the last artefact label on the read wins, as it does in `score`
+                if artefact.is_none_or(|(index, _)| index < first.index) {
+                    artefact = Some((first.index, found));
+                }
+            }
+        }
+        labels.sort_unstable();
+
+        scored.add_read(
+            strand,
+            chimeric,
+            &labels.into_iter().map(|(_, label)| label).collect_vec(),
+            artefact.map(|(_, artefact)| artefact),
+        );
+    }
 
-        if strand != b'-' && paf_reads[i].strand == '-' {
-            incorrect += 1;
-            println!("incorrect!");
+    for read in truth {
+        scored.add_missing(read.strand);
+    }
+
+    scored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TruthConfig;
+    use crate::flags::Flags;
+    use crate::header::{PychopperHeader, RestranderHeader};
+    use crate::paf::{self, PafRecord};
+
+    const TRUTH: [&str; 6] = [
+        "r1\t1000\t0\t1000\t+\ttx1\t5000\t0\t1000\t1000\t1000\t60",
+        "r2\t1000\t0\t1000\t-\ttx1\t5000\t0\t1000\t1000\t1000\t60",
+        "r3\t1000\t0\t1000\t+\ttx2\t5000\t0\t1000\t1000\t1000\t60",
+        "r4\t1000\t0\t1000\t-\ttx2\t5000\t0\t1000\t1000\t1000\t60",
+        "r5\t1000\t0\t400\t+\ttx1\t5000\t0\t400\t400\t400\t60",
+        "r5\t1000\t500\t1000\t-\ttx2\t5000\t0\t500\t500\t500\t60",
+    ];
+
+    fn truth(flags: &[&str]) -> PafReads {
+        let args = flags.iter().map(|flag| flag.to_string()).collect();
+        let config = TruthConfig::from_flags(&Flags::split_args(args).1);
+        let records = TRUTH.iter().map(|line| PafRecord::from_paf_line(line));
+        if config.low_memory {
+            paf::hashed_from_records(records, &config, None)
         } else {
-            correct += 1;
-            println!("correct!");
+            paf::from_records(records, &config, None)
         }
-        ambiguous += 1;
+    }
+
+    fn write_fastq(name: &str, headers: &[&str]) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "restrander-testing-rig-fastq-{}-{}.fq",
+            name,
+            std::process::id()
+        ));
+        let fastq = headers
+            .iter()
+            .map(|header| format!("@{header}\nACGT\n+\nIIII\n"))
+            .collect::<String>();
+        std::fs::write(&path, fastq).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn assert_same_scores(scored: &ScoredReads, expected: &ScoredReads) {
+        assert_eq!(scored.exact, expected.exact);
+        assert_eq!(scored.segments, expected.segments);
+        assert_eq!(scored.split_reads, expected.split_reads);
+        assert_eq!(scored.confusion, expected.confusion);
+        assert_eq!(scored.artefacts, expected.artefacts);
+    }
 
-        // println!("{}", strand_tag);
+    #[test]
+    fn low_memory_scores_match() {
+        let (named, hashed) = (truth(&[]), truth(&["--low-memory"]));
+        // each with the number of truth reads missing from it
+        let outputs: [(&str, &dyn HeaderParser, &[&str], u64); 2] = [
+            (
+                "restrander",
+                &RestranderHeader,
+                &["r1|+", "r2|+", "r3|?", "r5|+|TSO-TSO", "r7|-", "r1|-", ""],
+                1,
+            ),
+            (
+                "pychopper",
+                &PychopperHeader,
+                &[
+                    "0:10|r1 strand=+",
+                    "10:20|r1 strand=-",
+                    "0:10|r2 strand=-",
+                    "0:10|r2 strand=-",
+                    "10:20|r3 strand=+",
+                    "0:10|r9 strand=+",
+                    "garbage",
+                ],
+                2,
+            ),
+        ];
+
+        for (name, header_parser, headers, missing) in outputs {
+            let output = write_fastq(name, headers);
+            let expected = score(&output, &named, header_parser, 1);
+            let scored = score_low_memory(&output, &hashed, header_parser, 1);
+            std::fs::remove_file(output).unwrap();
+
+            assert_eq!(expected.exact.missing, missing);
+            assert_same_scores(&scored, &expected);
+
+            // the unparsed header is counted, but not listed as a read
+            assert_eq!(expected.exact.untruthed, 2);
+            assert_eq!(expected.categorised.untruthed.len(), 1);
+        }
     }
 
-    print!(
-        "Correct: {}\nIncorrect: {}\nAmbiguous: {}\n",
-        correct, incorrect, ambiguous
-    );
+    #[test]
+    fn threaded_scores_match() {
+        // enough records for many batches, split, duplicated and missing in places
+        let lines = (0..5000)
+            .filter(|i| i % 5 != 0)
+            .map(|i| {
+                let strand = if i % 2 == 0 { '+' } else { '-' };
+                format!("r{i}\t1000\t0\t1000\t{strand}\ttx\t5000\t0\t1000\t1000\t1000\t60")
+            })
+            .collect_vec();
+        let mut headers = vec![];
+        for i in (0..5000).filter(|i| i % 7 != 0) {
+            let label = ['+', '-', '?'][i % 3];
+            headers.push(format!("0:10|r{i} strand={label}"));
+            if i % 4 == 0 {
+                headers.push(format!("10:20|r{i} strand=+"));
+            }
+            if i % 6 == 0 {
+                headers.push(format!("0:10|r{i} strand=-"));
+            }
+            if i % 100 == 0 {
+                headers.push(format!("garbage{}", i % 300));
+            }
+        }
+        let output = write_fastq(
+            "threaded",
+            &headers.iter().map(String::as_str).collect_vec(),
+        );
+
+        let config = TruthConfig::from_flags(&Flags::split_args(vec![]).1);
+        let records = || lines.iter().map(|line| PafRecord::from_paf_line(line));
+        let named = paf::from_records(records(), &config, None);
+        let hashed = paf::hashed_from_records(records(), &config, None);
+
+        let expected = score(&output, &named, &PychopperHeader, 1);
+        for threads in [2, 4] {
+            let scored = score(&output, &named, &PychopperHeader, threads);
+            assert_same_scores(&scored, &expected);
+            assert_eq!(scored.categorised, expected.categorised);
+
+            let low_memory = score_low_memory(&output, &hashed, &PychopperHeader, threads);
+            assert_same_scores(&low_memory, &expected);
+        }
+        std::fs::remove_file(output).unwrap();
+    }
 }
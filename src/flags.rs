@@ -0,0 +1,97 @@
+use std::{cell::RefCell, collections::HashMap, fmt::Debug, str::FromStr};
+
+/// Optional `--name=value` (or bare `--name`) arguments, which may appear anywhere
+/// on the command line alongside the positional ones.
+pub struct Flags {
+    values: HashMap<String, String>,
+    used: RefCell<Vec<String>>,
+}
+
+impl Flags {
+    /// Split the raw arguments into positional arguments and flags.
+    pub fn split_args(args: Vec<String>) -> (Vec<String>, Flags) {
+        let (flags, positional): (Vec<String>, Vec<String>) =
+            args.into_iter().partition(|arg| arg.starts_with("--"));
+
+        let values = flags
+            .into_iter()
+            .map(|flag| match flag[2..].split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None => (flag[2..].to_string(), "true".to_string()),
+            })
+            .collect();
+
+        (
+            positional,
+            Flags {
+                values,
+                used: RefCell::new(vec![]),
+            },
+        )
+    }
+
+    /// Get the value of a flag, if it was given.
+    pub fn get_opt<T>(&self, name: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Debug,
+    {
+        self.used.borrow_mut().push(name.to_string());
+        self.values.get(name).map(|value| {
+            value
+                .parse()
+                .unwrap_or_else(|e| panic!("Bad value for --{}: {} ({:?})", name, value, e))
+        })
+    }
+
+    /// Get the value of a flag, or the default if it wasn't given.
+    pub fn get<T>(&self, name: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: Debug,
+    {
+        self.get_opt(name).unwrap_or(default)
+    }
+
+    /// Panic if any flag was given that nothing asked for.
+    pub fn finish(&self) {
+        let used = self.used.borrow();
+        if let Some(name) = self.values.keys().find(|name| !used.contains(name)) {
+            panic!("Unknown flag: --{}", name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(args: &[&str]) -> (Vec<String>, Flags) {
+        Flags::split_args(args.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    #[test]
+    fn splits_flags_from_positional_arguments() {
+        let (positional, flags) = split(&["rig", "--min-mapq=20", "quick", "--ts-tag", "a.fq"]);
+        assert_eq!(positional, vec!["rig", "quick", "a.fq"]);
+        assert_eq!(flags.get("min-mapq", 0_u8), 20);
+        assert!(flags.get("ts-tag", false));
+        assert_eq!(flags.get("min-identity", 0.5), 0.5);
+        assert_eq!(flags.get_opt::<String>("annotation"), None);
+        flags.finish();
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown flag: --mni-mapq")]
+    fn rejects_unknown_flags() {
+        let (_, flags) = split(&["--mni-mapq=20"]);
+        flags.get("min-mapq", 0_u8);
+        flags.finish();
+    }
+
+    #[test]
+    #[should_panic(expected = "Bad value for --min-mapq")]
+    fn rejects_bad_values() {
+        split(&["--min-mapq=high"]).1.get("min-mapq", 0_u8);
+    }
+}
@@ -0,0 +1,280 @@
+use regex::Regex;
+
+use crate::artefact::Artefact;
+
+/// The read name and strand label pulled out of a tool's output header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedHeader<'a> {
+    pub name: &'a str,
+    /// Which part of the read this is, for tools that can split one read
+    /// into several output records. None if the record is the whole read.
+    pub segment: Option<&'a str>,
+    /// `+`, `-`, or `?` if the tool didn't call a strand.
+    pub strand: char,
+    /// An artefact the tool says the read has, if it reports them.
+    pub artefact: Option<Artefact>,
+}
+
+/// Knows where a stranding tool puts the read name and strand in its output
+/// headers. Shared between the threads scoring an output file.
+pub trait HeaderParser: Sync {
+    /// Parse a full header line (without the `@`). Returns None if the header
+    /// isn't in the expected format at all.
+    fn parse<'a>(&self, header: &'a str) -> Option<ParsedHeader<'a>>;
+
+    /// Whether the tool can label artefacts at all. If not, there's nothing to
+    /// score its artefact calls on.
+    fn reports_artefacts(&self) -> bool {
+        false
+    }
+}
+
+fn strand_from_str(strand: &str) -> char {
+    match strand {
+        "+" => '+',
+        "-" => '-',
+        _ => '?',
+    }
+}
+
+/// Find a `strand=X` comment among the whitespace-separated header fields.
+fn strand_comment(header: &str) -> Option<char> {
+    header
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("strand="))
+        .map(strand_from_str)
+}
+
+/// Restrander appends `|+`, `|-` or `|?` to the read ID, e.g. `read1|+ runid=...`.
+/// If the tag isn't on the ID, it's looked for at the end of the whole header.
+/// With `report-artefacts` on, artefact reads get a `|TSO-TSO` or `|RTP-RTP` tag
+/// in the same places.
+pub struct RestranderHeader;
+
+impl HeaderParser for RestranderHeader {
+    fn parse<'a>(&self, header: &'a str) -> Option<ParsedHeader<'a>> {
+        let id = header.split_whitespace().next()?;
+        let mut fields = id.split('|');
+        let name = fields.next()?;
+
+        let strand = fields
+            .find(|field| matches!(*field, "+" | "-" | "?"))
+            .or_else(|| header.trim_end().rsplit('|').next())
+            .map(strand_from_str)
+            .unwrap_or('?');
+
+        let artefact = id
+            .split('|')
+            .chain(header.trim_end().rsplit('|').next())
+            .find_map(Artefact::from_label);
+
+        Some(ParsedHeader {
+            name,
+            segment: None,
+            strand,
+            artefact,
+        })
+    }
+
+    fn reports_artefacts(&self) -> bool {
+        true
+    }
+}
+
+/// Pychopper writes `start:end|read_id strand=+`. A read can be split into
+/// several segments, each with its own `start:end`.
+pub struct PychopperHeader;
+
+impl HeaderParser for PychopperHeader {
+    fn parse<'a>(&self, header: &'a str) -> Option<ParsedHeader<'a>> {
+        let id = header.split_whitespace().next()?;
+        let (segment, name) = id.split_once('|')?;
+        let name = name.split('|').next()?;
+
+        let strand = strand_comment(header).unwrap_or('?');
+
+        Some(ParsedHeader {
+            name,
+            segment: Some(segment),
+            strand,
+            artefact: None,
+        })
+    }
+}
+
+/// Nanoprep keeps the read ID and adds a `strand=+` comment.
+pub struct NanoprepHeader;
+
+impl HeaderParser for NanoprepHeader {
+    fn parse<'a>(&self, header: &'a str) -> Option<ParsedHeader<'a>> {
+        let name = header.split_whitespace().next()?;
+
+        let strand = strand_comment(header).unwrap_or('?');
+
+        Some(ParsedHeader {
+            name,
+            segment: None,
+            strand,
+            artefact: None,
+        })
+    }
+}
+
+/// For any other tool: a regex with `name` and `strand` capture groups,
+/// matched against the whole header. An optional `segment` group tells
+/// apart several records split from the same read, and an optional
+/// `artefact` group picks up `TSO-TSO` or `RTP-RTP` labels.
+pub struct RegexHeader {
+    regex: Regex,
+}
+
+impl RegexHeader {
+    pub fn new(pattern: &str) -> RegexHeader {
+        let regex =
+            Regex::new(pattern).unwrap_or_else(|e| panic!("Bad header regex {}: {}", pattern, e));
+        for group in ["name", "strand"] {
+            if !regex.capture_names().any(|name| name == Some(group)) {
+                panic!("Header regex {} has no `{}` group", pattern, group);
+            }
+        }
+
+        RegexHeader { regex }
+    }
+}
+
+impl HeaderParser for RegexHeader {
+    fn parse<'a>(&self, header: &'a str) -> Option<ParsedHeader<'a>> {
+        let captures = self.regex.captures(header)?;
+
+        Some(ParsedHeader {
+            name: captures.name("name")?.as_str(),
+            segment: captures.name("segment").map(|segment| segment.as_str()),
+            strand: captures
+                .name("strand")
+                .map(|strand| strand_from_str(strand.as_str()))
+                .unwrap_or('?'),
+            artefact: captures
+                .name("artefact")
+                .and_then(|artefact| Artefact::from_label(artefact.as_str())),
+        })
+    }
+
+    fn reports_artefacts(&self) -> bool {
+        self.regex
+            .capture_names()
+            .any(|name| name == Some("artefact"))
+    }
+}
+
+/// Pick a header parser by name: `restrander`, `pychopper`, `nanoprep`,
+/// or `regex:<pattern>`.
+pub fn from_name(name: &str) -> Box<dyn HeaderParser> {
+    match name {
+        "restrander" => Box::new(RestranderHeader),
+        "pychopper" => Box::new(PychopperHeader),
+        "nanoprep" => Box::new(NanoprepHeader),
+        _ => match name.strip_prefix("regex:") {
+            Some(pattern) => Box::new(RegexHeader::new(pattern)),
+            None => panic!("Unknown header format {}", name),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed<'a>(
+        name: &'a str,
+        segment: Option<&'a str>,
+        strand: char,
+        artefact: Option<Artefact>,
+    ) -> Option<ParsedHeader<'a>> {
+        Some(ParsedHeader {
+            name,
+            segment,
+            strand,
+            artefact,
+        })
+    }
+
+    #[test]
+    fn parses_restrander_headers() {
+        let parser = RestranderHeader;
+        assert_eq!(
+            parser.parse("read1|+ runid=abc"),
+            parsed("read1", None, '+', None)
+        );
+        // the tag can also be at the end of the whole header
+        assert_eq!(
+            parser.parse("read1 runid=abc|-"),
+            parsed("read1", None, '-', None)
+        );
+        assert_eq!(
+            parser.parse("read1|?|TSO-TSO runid=abc"),
+            parsed("read1", None, '?', Some(Artefact::TsoTso))
+        );
+        assert_eq!(
+            parser.parse("read1 runid=abc|RTP-RTP"),
+            parsed("read1", None, '?', Some(Artefact::RtpRtp))
+        );
+        assert_eq!(parser.parse("read1"), parsed("read1", None, '?', None));
+        assert_eq!(parser.parse(""), None);
+        assert!(parser.reports_artefacts());
+    }
+
+    #[test]
+    fn parses_pychopper_headers() {
+        let parser = PychopperHeader;
+        assert_eq!(
+            parser.parse("0:100|read1 strand=-"),
+            parsed("read1", Some("0:100"), '-', None)
+        );
+        assert_eq!(
+            parser.parse("0:100|read1"),
+            parsed("read1", Some("0:100"), '?', None)
+        );
+        assert_eq!(parser.parse("read1 strand=+"), None);
+        assert!(!parser.reports_artefacts());
+    }
+
+    #[test]
+    fn parses_nanoprep_headers() {
+        let parser = NanoprepHeader;
+        assert_eq!(
+            parser.parse("read1 length=100 strand=+"),
+            parsed("read1", None, '+', None)
+        );
+        assert_eq!(
+            parser.parse("read1 strand=x"),
+            parsed("read1", None, '?', None)
+        );
+        assert_eq!(parser.parse(""), None);
+        assert!(!parser.reports_artefacts());
+    }
+
+    #[test]
+    fn parses_regex_headers() {
+        let parser = from_name(r"regex:^(?P<name>\S+) (?P<segment>\d+) (?P<strand>[+?-])");
+        assert_eq!(
+            parser.parse("read1 2 -"),
+            parsed("read1", Some("2"), '-', None)
+        );
+        assert_eq!(parser.parse("read1 - 2"), None);
+        assert!(!parser.reports_artefacts());
+
+        let parser = RegexHeader::new(r"^(?P<name>[^|]+)\|(?P<strand>.)(\|(?P<artefact>\S+))?");
+        assert_eq!(
+            parser.parse("read1|+|TSO-TSO"),
+            parsed("read1", None, '+', Some(Artefact::TsoTso))
+        );
+        assert_eq!(parser.parse("read1|+"), parsed("read1", None, '+', None));
+        assert!(parser.reports_artefacts());
+    }
+
+    #[test]
+    #[should_panic(expected = "no `strand` group")]
+    fn regex_headers_need_a_strand_group() {
+        RegexHeader::new(r"^(?P<name>\S+)");
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::{HashMap, HashSet};
+
+use seq_io::fastq::{Reader, Record};
+
+use crate::config::{LengthSource, ScoringConfig, Stratum};
+use crate::integrity::Fingerprint;
+use crate::primer::{self, PrimerConfiguration};
+use crate::reader;
+
+/// What scoring needs from a tool's input FASTQ. It's read once per input
+/// and shared by every run on that input, however many configs there are.
+/// The per-read properties are only filled in for the strata that use them,
+/// and the fingerprints only if the output sequences are being checked.
+#[derive(Debug, Default)]
+pub struct InputReads {
+    pub names: HashSet<String>,
+    pub lengths: HashMap<String, f64>,
+    pub qualities: HashMap<String, f64>,
+    pub primers: HashMap<String, PrimerConfiguration>,
+    pub sequences: HashMap<String, Fingerprint>,
+}
+
+/// The mean quality of a read, averaging the error probabilities rather than
+/// the Phred scores, so a few bad bases aren't hidden by many good ones.
+fn mean_quality(qual: &[u8]) -> f64 {
+    if qual.is_empty() {
+        return 0.0;
+    }
+    let error = qual
+        .iter()
+        .map(|q| 10_f64.powf(-(q.saturating_sub(33) as f64) / 10.0))
+        .sum::<f64>()
+        / qual.len() as f64;
+    -10.0 * error.log10()
+}
+
+impl InputReads {
+    pub fn load(filename: &str, scoring_config: &ScoringConfig) -> InputReads {
+        // nothing that runs under --low-memory looks at the input
+        if scoring_config.low_memory {
+            return InputReads::default();
+        }
+
+        let strata = &scoring_config.strata;
+        let lengths =
+            strata.contains(Stratum::Length) && scoring_config.length_source == LengthSource::Fastq;
+        let qualities = strata.contains(Stratum::Quality);
+        let primers = strata.contains(Stratum::Primers).then(|| {
+            scoring_config
+                .primer_protocol
+                .as_ref()
+                .expect("Stratifying by primers needs a --primer-protocol")
+        });
+
+        let mut reads = InputReads::default();
+        let mut reader = Reader::new(reader::open(filename));
+        while let Some(record) = reader.next() {
+            let record = record.expect("Error reading record");
+            let name = record.id().expect("Header is not valid UTF-8").to_string();
+
+            if lengths {
+                reads
+                    .lengths
+                    .insert(name.clone(), record.seq().len() as f64);
+            }
+            if qualities {
+                reads
+                    .qualities
+                    .insert(name.clone(), mean_quality(record.qual()));
+            }
+            if let Some(protocol) = primers {
+                let hits =
+                    primer::search_read(record.seq(), protocol, &scoring_config.primer_search);
+                reads
+                    .primers
+                    .insert(name.clone(), PrimerConfiguration::new(&hits));
+            }
+            if scoring_config.check_sequences {
+                reads
+                    .sequences
+                    .insert(name.clone(), Fingerprint::new(record.seq(), record.qual()));
+            }
+            reads.names.insert(name);
+        }
+        reads
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_error_probabilities() {
+        assert!((mean_quality(b"IIII") - 40.0).abs() < 1e-9);
+        // Q40 and Q10 average to an error of about 0.05, not Q25
+        assert!((mean_quality(b"I+") - 13.006).abs() < 1e-3);
+        assert_eq!(mean_quality(b""), 0.0);
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::{fmt, str};
+
+use seq_io::fastq::{Reader, Record};
+use serde_json::{json, Value};
+
+use crate::header::HeaderParser;
+use crate::input::InputReads;
+use crate::reader;
+use crate::sequence::reverse_complement;
+
+/// Enough of a read to tell whether another record holds the same sequence
+/// and quality, without keeping the bases themselves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fingerprint {
+    pub length: usize,
+    pub seq: u64,
+    pub qual: u64,
+}
+
+impl Fingerprint {
+    pub fn new(seq: &[u8], qual: &[u8]) -> Fingerprint {
+        Fingerprint {
+            length: seq.len(),
+            seq: bytes_hash(seq),
+            qual: bytes_hash(qual),
+        }
+    }
+}
+
+fn bytes_hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// Whether a tool's output records hold the sequences its strand labels say
+/// they should: `-` reads reverse complemented with the quality reversed,
+/// `+` and `?` reads exactly as they came in.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SequenceIntegrity {
+    /// Output records matched up to an input record and compared.
+    pub checked: u64,
+    /// Reads labelled `-` whose sequence isn't the reverse complement of the input.
+    pub not_reverse_complemented: u64,
+    /// Reads labelled `+` or `?` whose sequence isn't the input's.
+    pub modified: u64,
+    /// Reads with the right sequence but the wrong quality string.
+    pub quality_mismatches: u64,
+    /// Reads shorter than the input (or segment) they came from.
+    pub truncated: u64,
+    /// Parsed output records with no input read, or a segment that isn't a
+    /// `start:end` range inside the input read, so they can't be checked.
+    pub unmatched: u64,
+}
+
+/// The part of the input read a segment covers, if it's a `start:end` range.
+fn segment_range(segment: &str, len: usize) -> Option<(usize, usize)> {
+    let (start, end) = segment.split_once(':')?;
+    let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+    (start <= end && end <= len).then_some((start, end))
+}
+
+/// An output segment waiting for its input read to be sliced.
+struct PendingSegment {
+    start: usize,
+    end: usize,
+    strand: char,
+    output: Fingerprint,
+}
+
+impl SequenceIntegrity {
+    /// Check a tool's output against the fingerprints of its input. Whole
+    /// reads are checked as the output streams past; segments of reads are
+    /// kept as fingerprints and checked in one more pass over the input, which
+    /// only looks at the reads that were segmented.
+    pub fn new(
+        input: &str,
+        input_reads: &InputReads,
+        output: &str,
+        header_parser: &dyn HeaderParser,
+    ) -> SequenceIntegrity {
+        let mut integrity = SequenceIntegrity::default();
+        let mut pending: HashMap<String, Vec<PendingSegment>> = HashMap::new();
+
+        let mut reader = Reader::new(reader::open(output));
+        while let Some(record) = reader.next() {
+            let record = record.expect("Error reading record");
+            let header = str::from_utf8(record.head()).expect("Header is not valid UTF-8");
+
+            // unparsed headers are already counted by the output accounting
+            let Some(parsed) = header_parser.parse(header) else {
+                continue;
+            };
+            let Some(input) = input_reads.sequences.get(parsed.name) else {
+                integrity.unmatched += 1;
+                continue;
+            };
+
+            // turn `-` records back round, so they can be compared to the input
+            let output = match parsed.strand {
+                '-' => {
+                    let qual: Vec<u8> = record.qual().iter().rev().copied().collect();
+                    Fingerprint::new(&reverse_complement(record.seq()), &qual)
+                }
+                _ => Fingerprint::new(record.seq(), record.qual()),
+            };
+
+            match parsed.segment {
+                None => integrity.compare(input, &output, parsed.strand),
+                Some(segment) => {
+                    match segment_range(segment, input.length) {
+                        Some((start, end)) => pending
+                            .entry(parsed.name.to_string())
+                            .or_default()
+                            .push(PendingSegment {
+                                start,
+                                end,
+                                strand: parsed.strand,
+                                output,
+                            }),
+                        None => integrity.unmatched += 1,
+                    }
+                }
+            }
+        }
+
+        if pending.is_empty() {
+            return integrity;
+        }
+        let mut reader = Reader::new(reader::open(input));
+        while let Some(record) = reader.next() {
+            let record = record.expect("Error reading record");
+            let name = record.id().expect("Header is not valid UTF-8");
+            let Some(segments) = pending.remove(name) else {
+                continue;
+            };
+            let (seq, qual) = (record.seq(), record.qual());
+            for segment in segments {
+                let input = Fingerprint::new(
+                    &seq[segment.start..segment.end],
+                    &qual[segment.start..segment.end],
+                );
+                integrity.compare(&input, &segment.output, segment.strand);
+            }
+        }
+
+        integrity
+    }
+
+    /// Count one output record, already turned back to the input's strand.
+    fn compare(&mut self, input: &Fingerprint, output: &Fingerprint, strand: char) {
+        self.checked += 1;
+        if output.length == input.length && output.seq == input.seq {
+            if output.qual != input.qual {
+                self.quality_mismatches += 1;
+            }
+        } else if output.length < input.length {
+            self.truncated += 1;
+        } else if strand == '-' {
+            self.not_reverse_complemented += 1;
+        } else {
+            self.modified += 1;
+        }
+    }
+
+    /// All the checked records that weren't what they should be.
+    pub fn mismatches(&self) -> u64 {
+        self.not_reverse_complemented + self.modified + self.quality_mismatches + self.truncated
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "checked": self.checked,
+            "not_reverse_complemented": self.not_reverse_complemented,
+            "modified": self.modified,
+            "quality_mismatches": self.quality_mismatches,
+            "truncated": self.truncated,
+            "unmatched": self.unmatched,
+        })
+    }
+}
+
+impl fmt::Display for SequenceIntegrity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "sequences checked     {}", self.checked)?;
+        writeln!(f, "  not rev. comp.      {}", self.not_reverse_complemented)?;
+        writeln!(f, "  modified            {}", self.modified)?;
+        writeln!(f, "  quality mismatches  {}", self.quality_mismatches)?;
+        writeln!(f, "  truncated           {}", self.truncated)?;
+        write!(f, "sequences unmatched   {}", self.unmatched)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ScoringConfig;
+    use crate::flags::Flags;
+    use crate::header::{NanoprepHeader, PychopperHeader};
+
+    fn write_fastq(name: &str, records: &[(&str, &str, &str)]) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "restrander-testing-rig-integrity-{}-{}.fq",
+            name,
+            std::process::id()
+        ));
+        let fastq = records
+            .iter()
+            .map(|(header, seq, qual)| format!("@{header}\n{seq}\n+\n{qual}\n"))
+            .collect::<String>();
+        std::fs::write(&path, fastq).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn load_input(input: &str, flags: &[&str]) -> InputReads {
+        let args = flags.iter().map(|flag| flag.to_string()).collect();
+        InputReads::load(
+            input,
+            &ScoringConfig::from_flags(&Flags::split_args(args).1),
+        )
+    }
+
+    #[test]
+    fn fingerprints_only_when_checking() {
+        let input = write_fastq("off", &[("r1", "ACGT", "IIII")]);
+        assert!(load_input(&input, &[]).sequences.is_empty());
+        assert_eq!(
+            load_input(&input, &["--check-sequences=true"]).sequences["r1"],
+            Fingerprint::new(b"ACGT", b"IIII")
+        );
+        std::fs::remove_file(input).unwrap();
+    }
+
+    #[test]
+    fn checks_whole_reads() {
+        let input = write_fastq(
+            "whole-in",
+            &[("r1", "AACCGGTTAC", "ABCDEFGHIJ"), ("r2", "AAAC", "ABCD")],
+        );
+        let output = write_fastq(
+            "whole-out",
+            &[
+                ("r1 strand=+", "AACCGGTTAC", "ABCDEFGHIJ"),
+                ("r2 strand=-", "GTTT", "DCBA"),
+                ("r2 strand=-", "AAAC", "ABCD"),
+                ("r1 strand=+", "AACCGGTTAC", "ABCDEFGHII"),
+                ("r1 strand=?", "AACCG", "ABCDE"),
+                ("r1 strand=+", "AACCGGTTAA", "ABCDEFGHIJ"),
+                ("r9 strand=+", "ACGT", "IIII"),
+            ],
+        );
+        let input_reads = load_input(&input, &["--check-sequences=true"]);
+        let integrity = SequenceIntegrity::new(&input, &input_reads, &output, &NanoprepHeader);
+        std::fs::remove_file(input).unwrap();
+        std::fs::remove_file(output).unwrap();
+
+        assert_eq!(
+            integrity,
+            SequenceIntegrity {
+                checked: 6,
+                not_reverse_complemented: 1,
+                modified: 1,
+                quality_mismatches: 1,
+                truncated: 1,
+                unmatched: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn checks_segments_against_the_input() {
+        let input = write_fastq(
+            "segment-in",
+            &[("r1", "AACCGGTTAC", "ABCDEFGHIJ"), ("r2", "AAAC", "ABCD")],
+        );
+        let output = write_fastq(
+            "segment-out",
+            &[
+                ("0:4|r1 strand=+", "AACC", "ABCD"),
+                ("4:10|r1 strand=-", "GTAACC", "JIHGFE"),
+                ("4:10|r1 strand=-", "GGTTAC", "EFGHIJ"),
+                ("0:20|r1 strand=+", "AACC", "ABCD"),
+                ("0:4|r2 strand=+", "AAAC", "ABCD"),
+            ],
+        );
+        let input_reads = load_input(&input, &["--check-sequences=true"]);
+        let integrity = SequenceIntegrity::new(&input, &input_reads, &output, &PychopperHeader);
+        std::fs::remove_file(input).unwrap();
+        std::fs::remove_file(output).unwrap();
+
+        assert_eq!(
+            integrity,
+            SequenceIntegrity {
+                checked: 4,
+                not_reverse_complemented: 1,
+                unmatched: 1,
+                ..SequenceIntegrity::default()
+            }
+        );
+    }
+}
@@ -1,12 +1,9 @@
-use std::{fmt::format, fs::File, io::Write, path::Path};
+use std::{fs::File, io::Write};
 
-use itertools::{iproduct, Itertools};
-use serde_json::{error, json, Value};
+use itertools::Itertools;
+use serde_json::{json, Value};
 
-use crate::{
-    config::{self, Protocol},
-    constants,
-};
+use crate::config::Protocol;
 
 pub type Pipeline = Vec<Method>;
 
@@ -18,13 +15,16 @@ pub struct Config {
 }
 
 impl Config {
+    #[allow(dead_code)]
     const DEFAULT_ERROR_RATE: f64 = 0.25;
     const DEFAULT_EXCLUDE_UNKNOWNS: bool = false;
 
+    #[allow(dead_code)]
     fn default_pipeline(protocol: &Protocol) -> Pipeline {
         vec![Method::default_poly(), Method::Primer(protocol.clone())]
     }
 
+    #[allow(dead_code)]
     fn default_config() -> Config {
         Config {
             pipeline: Config::default_pipeline(&Protocol::PCB109),
@@ -46,11 +46,12 @@ impl Config {
         })
     }
 
-    pub fn to_filename(self) -> String {
+    #[allow(dead_code)]
+    pub fn to_filename(&self) -> String {
         // collect and concatenate all of the pipeline steps into one string
         let pipeline_string = self
             .pipeline
-            .into_iter()
+            .iter()
             .map(|method| method.to_filename())
             .fold("".to_string(), |a, b| format!("{}+{}", a, b));
 
@@ -63,15 +64,18 @@ impl Config {
 
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum Method {
+    #[allow(dead_code)]
     Poly(u64, u64),
     Primer(Protocol),
 }
 
 impl Method {
+    #[allow(dead_code)]
     fn default_poly() -> Method {
         Method::Poly(10, 200)
     }
 
+    #[allow(dead_code)]
     fn default_primer() -> Method {
         Method::Primer(Protocol::PCB109)
     }
@@ -83,23 +87,16 @@ impl Method {
                 "tail-length": tail_length,
                 "search-size": search_size
             }),
-            Method::Primer(protocol) => match protocol {
-                Protocol::PCB109 => json!({
-                    "type": "primer",
-                    "tso": "TTTCTGTTGGTGCTGATATTGCTGGG",
-                    "rtp": "ACTTGCCTGTCGCTCTATCTTCTTTTTTTTTT",
-                    "report-artefacts": true
-                }),
-                Protocol::PCB111 => json!({
-                    "type": "primer",
-                    "tso": "TTTCTGTTGGTGCTGATATTGCTTT",
-                    "rtp": "CTTGCCTGTCGCTCTATCTTCAGAGGAG",
-                    "report-artefacts": true
-                }),
-            },
+            Method::Primer(protocol) => json!({
+                "type": "primer",
+                "tso": protocol.tso(),
+                "rtp": protocol.rtp(),
+                "report-artefacts": true
+            }),
         }
     }
 
+    #[allow(dead_code)]
     fn to_filename(&self) -> String {
         match self {
             Method::Poly(tail_length, search_size) => {
@@ -113,6 +110,7 @@ impl Method {
     }
 }
 
+#[allow(dead_code)]
 pub fn make_desired_configs(config_dir: &str, protocol: &Protocol) {
     // make error rate configs
     let error_rates = vec![
@@ -171,7 +169,7 @@ pub fn pcb111_protocol_testing(config_dir: &str, protocol: &Protocol) {
 fn save_config(path: &str, config: &Config) -> String {
     // make the file
     File::create(path)
-        .expect(&format!("Failed to create file {}", path))
+        .unwrap_or_else(|_| panic!("Failed to create file {}", path))
         .write_all(config.clone().to_json().to_string().as_bytes())
         .expect("Failed to write to file!");
 
@@ -179,6 +177,7 @@ fn save_config(path: &str, config: &Config) -> String {
     path.to_string()
 }
 
+#[allow(dead_code)]
 fn make_error_rate_config(error_rate: f64, protocol: &Protocol) -> Config {
     Config {
         pipeline: Config::default_pipeline(protocol),
@@ -195,6 +194,7 @@ fn make_error_rate_config_no_poly(error_rate: f64, protocol: Protocol) -> Config
     }
 }
 
+#[allow(dead_code)]
 fn make_search_size_config(search_size: u64, protocol: Protocol) -> Config {
     Config {
         pipeline: vec![Method::Poly(10, search_size), Method::Primer(protocol)],
@@ -203,6 +203,7 @@ fn make_search_size_config(search_size: u64, protocol: Protocol) -> Config {
     }
 }
 
+#[allow(dead_code)]
 fn make_no_primer_config() -> Config {
     Config {
         pipeline: vec![Method::default_poly()],
@@ -211,6 +212,7 @@ fn make_no_primer_config() -> Config {
     }
 }
 
+#[allow(dead_code)]
 fn make_no_poly_config(protocol: Protocol) -> Config {
     Config {
         pipeline: vec![Method::Primer(protocol)],
@@ -1,20 +1,49 @@
 use core::panic;
-use std::{env, fs};
+use std::{collections::HashMap, env, fs, fs::File, io::Write};
 
+use accounting::OutputAccounting;
 use config::{
-    GenericProgramConfig, ProgramResult, Protocol, PychopperConfig, SpecificProgramConfig,
+    GenericProgramConfig, ProgramResult, Protocol, PychopperConfig, ScoringConfig,
+    SpecificProgramConfig, Tool, Tools, TruthConfig,
 };
+use fastq::{AccuracyIntervals, AccuracyResult};
+use flags::Flags;
+use input::InputReads;
+use integrity::SequenceIntegrity;
 use itertools::{iproduct, Itertools};
+use metrics::{csv_value, ClassificationMetrics, Interval};
+use nanoprep::NanoprepArgs;
+use paf::PafReads;
 use restrander::accuracy_timed_run_config;
+use serde_json::json;
 
+mod accounting;
+mod annotation;
+mod artefact;
+mod cache;
+mod command;
 mod comparison;
 mod config;
 mod constants;
 mod fastq;
+mod flags;
+mod header;
+mod input;
+mod integrity;
 mod json;
+mod metrics;
+mod nanoprep;
 mod paf;
+mod primer;
 mod pychopper;
+mod reader;
+mod reference;
 mod restrander;
+mod sam;
+mod sequence;
+mod stats;
+mod strata;
+mod truth;
 
 enum ProgramInput {
     GridTest {
@@ -42,14 +71,17 @@ enum ProgramInput {
     Quick {
         fastq: String,
         paf: String,
+        header: String,
+    },
+    TruthStats {
+        fastq: String,
+        paf: String,
     },
 }
 
 impl ProgramInput {
-    fn new_from_args() -> ProgramInput {
-        let args: Vec<String> = env::args().collect();
-
-        if args.is_empty() {
+    fn new_from_args(args: &[String]) -> ProgramInput {
+        if args.len() < 2 {
             panic!("No argument given!")
         }
 
@@ -79,6 +111,14 @@ impl ProgramInput {
             "quick" => ProgramInput::Quick {
                 fastq: args[2].clone(),
                 paf: args[3].clone(),
+                header: args
+                    .get(4)
+                    .cloned()
+                    .unwrap_or_else(|| "restrander".to_string()),
+            },
+            "truth-stats" => ProgramInput::TruthStats {
+                fastq: args[2].clone(),
+                paf: args[3].clone(),
             },
             _ => panic!("Invalid first argument: {}", args[1]),
         }
@@ -86,8 +126,44 @@ impl ProgramInput {
 }
 
 fn main() {
-    // load the generic input
-    let input = ProgramInput::new_from_args();
+    // load the generic input, and any optional flags
+    let (args, flags) = Flags::split_args(env::args().collect());
+    let input = ProgramInput::new_from_args(&args);
+    let truth_config = TruthConfig::from_flags(&flags);
+    let mut scoring_config = ScoringConfig::from_flags(&flags);
+    let tools: Tools = flags.get("tools", Tools::default());
+    let json_output: Option<String> = flags.get_opt("json");
+    let input_fastq: Option<String> = flags.get_opt("input-fastq");
+    let nanoprep_args: Option<NanoprepArgs> = flags.get_opt("nanoprep-args");
+    flags.finish();
+
+    if tools.contains(Tool::Nanoprep)
+        && nanoprep_args.is_none()
+        && matches!(
+            input,
+            ProgramInput::GridTest { .. } | ProgramInput::Standard { .. }
+        )
+    {
+        panic!("nanoprep needs its command line, e.g. --nanoprep-args=\"{{input}} {{output}}\"");
+    }
+
+    if truth_config.low_memory
+        && matches!(
+            input,
+            ProgramInput::CompareReads { .. } | ProgramInput::TruthStats { .. }
+        )
+    {
+        panic!("--low-memory is only for scoring, compare and truth-stats need the read names");
+    }
+
+    // look for the run's own primers unless told otherwise
+    if let ProgramInput::GridTest { protocol, .. } | ProgramInput::Standard { protocol, .. } =
+        &input
+    {
+        scoring_config
+            .primer_protocol
+            .get_or_insert_with(|| protocol.clone());
+    }
 
     // send the program down the appropriate branch
     match input {
@@ -97,13 +173,21 @@ fn main() {
             config_dir,
             temp_fastq,
             protocol,
-        } => grid_test(&GridTestInput {
-            fastq,
-            paf,
-            config_dir,
-            temp_fastq,
-            protocol,
-        }),
+        } => report_results(
+            &grid_test(&GridTestInput {
+                fastq,
+                paf,
+                config_dir,
+                temp_fastq,
+                protocol,
+                truth_config,
+                scoring_config: scoring_config.clone(),
+                tools,
+                nanoprep_args,
+            }),
+            json_output.as_deref(),
+            &scoring_config,
+        ),
         ProgramInput::CompareReads {
             fastq,
             paf,
@@ -118,6 +202,8 @@ fn main() {
             &temp_fastq,
             &output_directory,
             &protocol,
+            &truth_config,
+            &scoring_config,
         ),
         ProgramInput::Standard {
             fastq,
@@ -125,19 +211,118 @@ fn main() {
             restrander_config,
             temp_fastq,
             protocol,
-        } => standard(&fastq, &paf, &restrander_config, &temp_fastq, &protocol),
-        ProgramInput::Quick { fastq, paf } => quick(&fastq, &paf),
+        } => report_results(
+            &standard(
+                &fastq,
+                &paf,
+                &restrander_config,
+                &temp_fastq,
+                &protocol,
+                &tools,
+                nanoprep_args.as_ref(),
+                &truth_config,
+                &scoring_config,
+            ),
+            json_output.as_deref(),
+            &scoring_config,
+        ),
+        ProgramInput::Quick { fastq, paf, header } => quick(
+            &fastq,
+            &paf,
+            &header,
+            input_fastq.as_deref(),
+            &truth_config,
+            &scoring_config,
+        ),
+        ProgramInput::TruthStats { fastq, paf } => {
+            truth_stats(&fastq, &paf, &truth_config, json_output.as_deref())
+        }
+    }
+}
+
+fn truth_stats(fastq: &str, paf: &str, truth_config: &TruthConfig, json_output: Option<&str>) {
+    let stats = stats::TruthStats::new(fastq, paf, truth_config);
+
+    println!("{}", stats);
+    if let Some(path) = json_output {
+        save_json(path, &stats.to_json());
     }
 }
 
-fn quick(fastq: &str, paf: &str) {
-    let paf_reads = paf::parse(paf);
+fn save_json(path: &str, value: &serde_json::Value) {
+    File::create(path)
+        .unwrap_or_else(|_| panic!("Failed to create file {}", path))
+        .write_all(serde_json::to_string_pretty(value).unwrap().as_bytes())
+        .expect("Failed to write to file!");
+}
+
+fn quick(
+    fastq: &str,
+    paf: &str,
+    header: &str,
+    input_fastq: Option<&str>,
+    truth_config: &TruthConfig,
+    scoring_config: &ScoringConfig,
+) {
+    let paf_reads = truth::load(paf, truth_config);
+    let header_parser = header::from_name(header);
+    // under --low-memory there are no names to check the input against
+    let input_reads = input_fastq
+        .filter(|_| !scoring_config.low_memory)
+        .map(|input_fastq| InputReads::load(input_fastq, scoring_config));
+
+    let scored = fastq::score_with(fastq, &paf_reads, header_parser.as_ref(), scoring_config);
+
+    println!("{}", scored.exact.accuracy(scoring_config));
+    println!("split reads {}", scored.split_reads);
+    println!("segments\n{}", scored.segments.accuracy(scoring_config));
+    if scored.artefacts.reported {
+        println!("{}", scored.artefacts);
+    }
+    for stratified in strata::stratify(
+        input_reads.as_ref(),
+        &paf_reads,
+        &scored.categorised,
+        scoring_config,
+    ) {
+        for bin in &stratified.bins {
+            println!("{} {} ({} reads)", stratified.name, bin.label, bin.reads);
+            println!("{}", bin.accuracy);
+        }
+    }
+    println!("{}", scored.confusion);
+    println!("{}", scored.confusion.metrics());
+    if let Some(bootstrap) = scoring_config.bootstrap {
+        let intervals = scored.confusion.bootstrap(&bootstrap);
+        for (name, interval) in ClassificationMetrics::NAMES.iter().zip(intervals) {
+            println!("{} bootstrap {}", name.replace('_', " "), interval);
+        }
+    }
 
-    let result = fastq::parse(fastq, &paf_reads, false);
+    // only possible if we know what the tool was given
+    if let (Some(input_fastq), Some(input_reads)) = (input_fastq, &input_reads) {
+        let accounting =
+            OutputAccounting::new(input_reads, fastq, &paf_reads, header_parser.as_ref());
+        println!("{}", accounting);
+        if scoring_config.strict_accounting && !accounting.balanced() {
+            panic!("Output accounting doesn't balance");
+        }
+        if scoring_config.check_sequences {
+            println!(
+                "{}",
+                SequenceIntegrity::new(input_fastq, input_reads, fastq, header_parser.as_ref())
+            );
+        }
+    }
 
-    println!("{}", result);
+    if let Some(path) = &scoring_config.reference_tsv {
+        let references =
+            reference::aggregate_references(&paf_reads, &scored.categorised, scoring_config);
+        reference::save_tsv(path, &[(header.to_string(), references.as_slice())]);
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn compare(
     fastq: &str,
     paf: &str,
@@ -145,13 +330,15 @@ fn compare(
     temp_fastq: &str,
     output_directory: &str,
     protocol: &Protocol,
+    truth_config: &TruthConfig,
+    scoring_config: &ScoringConfig,
 ) {
     let pychopper_config = SpecificProgramConfig::Pychopper(PychopperConfig {
         backend: config::PychopperBackend::MachineLearning,
         protocol: protocol.clone(),
     });
 
-    let paf_reads = paf::parse(paf);
+    let paf_reads = truth::load(paf, truth_config);
 
     comparison::compare(
         fastq,
@@ -160,74 +347,119 @@ fn compare(
         &paf_reads,
         temp_fastq,
         output_directory,
+        &header::RestranderHeader,
+        &header::PychopperHeader,
+        scoring_config.threads,
     );
 }
 
+#[allow(clippy::too_many_arguments)]
 fn standard(
     fastq: &str,
     paf: &str,
     restrander_config: &str,
     temp_fastq: &str,
     protocol: &Protocol,
-) {
-    let paf_reads = paf::parse(paf);
+    tools: &Tools,
+    nanoprep_args: Option<&NanoprepArgs>,
+    truth_config: &TruthConfig,
+    scoring_config: &ScoringConfig,
+) -> Vec<ProgramResult> {
+    let paf_reads = truth::load(paf, truth_config);
+    let input_reads = InputReads::load(fastq, scoring_config);
     let generic_config: GenericProgramConfig = GenericProgramConfig {
         input: fastq.to_string(),
         output: temp_fastq.to_string(),
     };
 
-    let restrander_result =
-        accuracy_timed_run_config(&generic_config, restrander_config, &paf_reads);
+    let mut results = vec![];
 
-    let pychopper_edlib_result = pychopper::accuracy_timed_run_config(
-        &generic_config,
-        &SpecificProgramConfig::Pychopper(PychopperConfig {
-            backend: config::PychopperBackend::Edlib,
-            protocol: protocol.clone(),
-        }),
-        &paf_reads,
-    );
-    let pychopper_ml_result = pychopper::accuracy_timed_run_config(
-        &generic_config,
-        &SpecificProgramConfig::Pychopper(PychopperConfig {
-            backend: config::PychopperBackend::MachineLearning,
-            protocol: protocol.clone(),
-        }),
-        &paf_reads,
-    );
+    if tools.contains(Tool::Restrander) {
+        results.push(accuracy_timed_run_config(
+            &generic_config,
+            restrander_config,
+            &paf_reads,
+            &input_reads,
+            scoring_config,
+        ));
+    }
+
+    if tools.contains(Tool::Pychopper) {
+        results.push(pychopper::accuracy_timed_run_config(
+            &generic_config,
+            &SpecificProgramConfig::Pychopper(PychopperConfig {
+                backend: config::PychopperBackend::Edlib,
+                protocol: protocol.clone(),
+            }),
+            &paf_reads,
+            &input_reads,
+            scoring_config,
+        ));
+        results.push(pychopper::accuracy_timed_run_config(
+            &generic_config,
+            &SpecificProgramConfig::Pychopper(PychopperConfig {
+                backend: config::PychopperBackend::MachineLearning,
+                protocol: protocol.clone(),
+            }),
+            &paf_reads,
+            &input_reads,
+            scoring_config,
+        ));
+    }
+
+    if tools.contains(Tool::Nanoprep) {
+        results.push(nanoprep::accuracy_timed_run_config(
+            &generic_config,
+            nanoprep_args.expect("nanoprep needs --nanoprep-args"),
+            &paf_reads,
+            &input_reads,
+            scoring_config,
+        ));
+    }
 
-    print_results(&[
-        restrander_result,
-        pychopper_edlib_result,
-        pychopper_ml_result,
-    ]);
+    results
 }
 
-fn grid_test(input: &GridTestInput) {
-    // make the configs
-    json::pcb111_protocol_testing(&input.clone().config_dir, &input.clone().protocol);
-
-    // get all the configs from the given config location
-    let restrander_configs = get_paths(&input.clone().config_dir);
-    let pychopper_configs = vec![
-        SpecificProgramConfig::Pychopper(PychopperConfig {
-            backend: config::PychopperBackend::Edlib,
-            protocol: input.clone().protocol,
-        }),
-        SpecificProgramConfig::Pychopper(PychopperConfig {
-            backend: config::PychopperBackend::MachineLearning,
-            protocol: input.clone().protocol,
-        }),
-    ];
+fn grid_test(input: &GridTestInput) -> Vec<ProgramResult> {
+    // make the configs, and get them all from the given config location
+    let restrander_configs = if input.tools.contains(Tool::Restrander) {
+        json::pcb111_protocol_testing(&input.clone().config_dir, &input.clone().protocol);
+        get_paths(&input.clone().config_dir)
+    } else {
+        vec![]
+    };
+    let pychopper_configs = if input.tools.contains(Tool::Pychopper) {
+        vec![
+            SpecificProgramConfig::Pychopper(PychopperConfig {
+                backend: config::PychopperBackend::Edlib,
+                protocol: input.clone().protocol,
+            }),
+            SpecificProgramConfig::Pychopper(PychopperConfig {
+                backend: config::PychopperBackend::MachineLearning,
+                protocol: input.clone().protocol,
+            }),
+        ]
+    } else {
+        vec![]
+    };
+
+    // parse the truth and read the input once, and share them between every run
+    let inputs = [(
+        input.clone(),
+        truth::load(&input.paf, &input.truth_config),
+        InputReads::load(&input.fastq, &input.scoring_config),
+    )];
 
     // perform the grid test as configured
-    let results = restrander_grid_test(&[input.clone()], &restrander_configs)
+    restrander_grid_test(&inputs, &restrander_configs)
         .into_iter()
-        .chain(pychopper_grid_test(&[input.clone()], &pychopper_configs))
-        .collect_vec();
-
-    // prettyprint it
-    print_results(&results);
+        .chain(pychopper_grid_test(&inputs, &pychopper_configs))
+        .chain(if input.tools.contains(Tool::Nanoprep) {
+            nanoprep_grid_test(&inputs)
+        } else {
+            vec![]
+        })
+        .collect_vec()
 }
 
 // get config paths
@@ -245,11 +477,17 @@ struct GridTestInput {
     config_dir: String,
     temp_fastq: String,
     protocol: Protocol,
+    truth_config: TruthConfig,
+    scoring_config: ScoringConfig,
+    tools: Tools,
+    /// Only needed if nanoprep is one of the tools.
+    nanoprep_args: Option<NanoprepArgs>,
 }
 
 impl GridTestInput {
+    #[allow(dead_code)]
     pub fn new_from_args() -> GridTestInput {
-        let args: Vec<String> = env::args().collect();
+        let (args, flags) = Flags::split_args(env::args().collect());
 
         assert!(args.len() == 6);
         GridTestInput {
@@ -258,6 +496,10 @@ impl GridTestInput {
             config_dir: args[3].clone(),
             temp_fastq: args[4].clone(),
             protocol: Protocol::new(args[5].clone().as_str()),
+            truth_config: TruthConfig::from_flags(&flags),
+            scoring_config: ScoringConfig::from_flags(&flags),
+            tools: flags.get("tools", Tools::default()),
+            nanoprep_args: flags.get_opt("nanoprep-args"),
         }
     }
 }
@@ -284,64 +526,281 @@ fn _generate_error_rates(max: f64, step: i32) -> Vec<f64> {
 // }
 
 fn restrander_grid_test(
-    inputs: &[GridTestInput],
+    inputs: &[(GridTestInput, PafReads, InputReads)],
     configs: &[String],
 ) -> Vec<config::ProgramResult> {
     // run restrander on the product of inputs and configs
     iproduct!(inputs, configs)
-        .map(|(input, config)| {
-            (
-                config::GenericProgramConfig {
+        .map(|((input, paf_reads, input_reads), config)| {
+            restrander::accuracy_timed_run_config(
+                &config::GenericProgramConfig {
                     input: input.fastq.clone(),
                     output: input.temp_fastq.to_string(),
                 },
                 config,
-                paf::parse(&input.paf),
+                paf_reads,
+                input_reads,
+                &input.scoring_config,
             )
         })
-        .map(|(generic_config, restrander_config, paf_reads)| {
-            restrander::accuracy_timed_run_config(&generic_config, restrander_config, &paf_reads)
-        })
         .collect()
 }
 
 fn pychopper_grid_test(
-    inputs: &[GridTestInput],
+    inputs: &[(GridTestInput, PafReads, InputReads)],
     configs: &[SpecificProgramConfig],
 ) -> Vec<config::ProgramResult> {
     // run pychopper on the product of inputs and configs
     iproduct!(inputs, configs)
-        .map(|(input, config)| {
-            (
-                config::GenericProgramConfig {
+        .map(|((input, paf_reads, input_reads), config)| {
+            pychopper::accuracy_timed_run_config(
+                &config::GenericProgramConfig {
                     input: input.fastq.clone(),
                     output: input.temp_fastq.clone(),
                 },
                 config,
-                paf::parse(&input.paf),
+                paf_reads,
+                input_reads,
+                &input.scoring_config,
             )
         })
-        .map(|(generic_config, restrander_config, paf_reads)| {
-            pychopper::accuracy_timed_run_config(&generic_config, restrander_config, &paf_reads)
+        .collect()
+}
+
+fn nanoprep_grid_test(
+    inputs: &[(GridTestInput, PafReads, InputReads)],
+) -> Vec<config::ProgramResult> {
+    // nanoprep has no configs to search over, so just run it on each input
+    inputs
+        .iter()
+        .map(|(input, paf_reads, input_reads)| {
+            nanoprep::accuracy_timed_run_config(
+                &config::GenericProgramConfig {
+                    input: input.fastq.clone(),
+                    output: input.temp_fastq.clone(),
+                },
+                input
+                    .nanoprep_args
+                    .as_ref()
+                    .expect("nanoprep needs --nanoprep-args"),
+                paf_reads,
+                input_reads,
+                &input.scoring_config,
+            )
         })
         .collect()
 }
 
+fn report_results(
+    results: &[ProgramResult],
+    json_output: Option<&str>,
+    scoring_config: &ScoringConfig,
+) {
+    print_results(results);
+    if let Some(path) = json_output {
+        save_json(path, &results_to_json(results));
+    }
+    if let Some(path) = &scoring_config.reference_tsv {
+        let references = results
+            .iter()
+            .map(|result| {
+                (
+                    result.config.specific.to_string(),
+                    result.references.as_slice(),
+                )
+            })
+            .collect_vec();
+        reference::save_tsv(path, &references);
+    }
+}
+
+/// `(name, value)` pairs for one result's accuracy, with a prefix on every name.
+fn accuracy_columns(prefix: &str, accuracy: &AccuracyResult) -> Vec<(String, String)> {
+    let values = [
+        accuracy.correct,
+        accuracy.incorrect,
+        accuracy.ambiguous,
+        accuracy.untruthed,
+    ];
+
+    let mut columns = vec![];
+    for (name, value) in AccuracyIntervals::NAMES.iter().zip(values) {
+        columns.push((format!("{}{}_percent", prefix, name), csv_value(value)));
+    }
+    for (name, interval) in AccuracyIntervals::NAMES
+        .iter()
+        .zip(accuracy.intervals.values())
+    {
+        columns.push((
+            format!("{}{}_percent_low", prefix, name),
+            csv_value(interval.low),
+        ));
+        columns.push((
+            format!("{}{}_percent_high", prefix, name),
+            csv_value(interval.high),
+        ));
+    }
+    columns
+}
+
+/// `(name, value)` pairs for every CSV column of a result.
+fn result_columns(result: &ProgramResult) -> Vec<(String, String)> {
+    let mut columns = vec![("config".to_string(), result.config.specific.to_string())];
+    columns.extend(accuracy_columns("", &result.accuracy));
+    columns.push(("time_secs".to_string(), result.duration.to_string()));
+
+    let metrics = result.confusion.metrics();
+    for (name, value) in ClassificationMetrics::NAMES.iter().zip(metrics.values()) {
+        columns.push((name.to_string(), csv_value(value)));
+    }
+    if let Some(intervals) = &result.metric_intervals {
+        for (name, interval) in ClassificationMetrics::NAMES.iter().zip(intervals) {
+            columns.push((format!("{}_low", name), csv_value(interval.low)));
+            columns.push((format!("{}_high", name), csv_value(interval.high)));
+        }
+    }
+
+    columns.extend(accuracy_columns("segment_", &result.segment_accuracy));
+    columns.push(("split_reads".to_string(), result.split_reads.to_string()));
+
+    if let Some(accounting) = &result.accounting {
+        for (name, count) in [
+            ("input_reads", accounting.input_reads),
+            ("output_records", accounting.output_records),
+            ("unparsed", accounting.unparsed),
+            ("duplicates", accounting.duplicates),
+            ("extras", accounting.extras),
+            ("drops", accounting.drops),
+        ] {
+            columns.push((name.to_string(), count.to_string()));
+        }
+    }
+
+    // only runs whose sequences were checked have these columns
+    if let Some(integrity) = &result.integrity {
+        for (name, count) in [
+            ("sequences_checked", integrity.checked),
+            (
+                "not_reverse_complemented",
+                integrity.not_reverse_complemented,
+            ),
+            ("modified", integrity.modified),
+            ("quality_mismatches", integrity.quality_mismatches),
+            ("truncated", integrity.truncated),
+        ] {
+            columns.push((name.to_string(), count.to_string()));
+        }
+    }
+
+    // tools that can't label artefacts leave these columns empty
+    let artefacts = &result.artefacts;
+    if artefacts.reported {
+        columns.push((
+            "artefact_precision".to_string(),
+            csv_value(artefacts.precision()),
+        ));
+        columns.push(("artefact_recall".to_string(), csv_value(artefacts.recall())));
+        columns.push(("tso_tso".to_string(), artefacts.tso_tso.to_string()));
+        columns.push(("rtp_rtp".to_string(), artefacts.rtp_rtp.to_string()));
+    }
+
+    for stratified in &result.strata {
+        for bin in &stratified.bins {
+            let prefix = format!("{}_{}_", stratified.name, bin.label);
+            columns.push((format!("{}reads", prefix), bin.reads.to_string()));
+            columns.extend(accuracy_columns(&prefix, &bin.accuracy));
+        }
+    }
+
+    for (name, count) in result.confusion.columns() {
+        columns.push((name, count.to_string()));
+    }
+    columns
+}
+
 fn print_results(results: &[ProgramResult]) {
-    // print CSV header line
-    println!("config,correct_percent,incorrect_percent,ambiguous_percent,time_secs");
-
-    // print each result
-    results.iter().for_each(|result| {
-        println!(
-            "{},{},{},{},{}",
-            result.config.specific,
-            result.accuracy.correct,
-            result.accuracy.incorrect,
-            result.accuracy.ambiguous,
-            result.duration
-        )
-    });
+    let rows = results.iter().map(result_columns).collect_vec();
+    for line in csv_lines(&rows) {
+        println!("{}", line);
+    }
+}
+
+/// The CSV lines for a set of rows, header first. Results can have different
+/// columns (strata, artefacts), so the header is every column in any row,
+/// keeping each row's column order, and rows leave the columns they lack empty.
+fn csv_lines(rows: &[Vec<(String, String)>]) -> Vec<String> {
+    let mut header: Vec<&str> = vec![];
+    for row in rows {
+        let mut position = 0;
+        for (name, _) in row {
+            match header.iter().position(|column| column == name) {
+                Some(i) => position = i + 1,
+                None => {
+                    header.insert(position, name);
+                    position += 1;
+                }
+            }
+        }
+    }
+
+    if header.is_empty() {
+        return vec![];
+    }
+
+    let mut lines = vec![header.join(",")];
+    for row in rows {
+        let values: HashMap<&str, &str> = row
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        lines.push(
+            header
+                .iter()
+                .map(|column| values.get(column).copied().unwrap_or(""))
+                .join(","),
+        );
+    }
+    lines
+}
+
+fn results_to_json(results: &[ProgramResult]) -> serde_json::Value {
+    results
+        .iter()
+        .map(|result| {
+            json!({
+                "config": result.config.specific.to_string(),
+                "accuracy": result.accuracy.to_json(),
+                "time_secs": result.duration,
+                "metrics": result.confusion.metrics().to_json(),
+                "metric_intervals": result.metric_intervals.as_ref().map(|intervals| {
+                    intervals_to_json(&ClassificationMetrics::NAMES, intervals)
+                }),
+                "segment_accuracy": result.segment_accuracy.to_json(),
+                "split_reads": result.split_reads,
+                "accounting": result.accounting.as_ref().map(OutputAccounting::to_json),
+                "integrity": result.integrity.as_ref().map(SequenceIntegrity::to_json),
+                "artefacts": result
+                    .artefacts
+                    .reported
+                    .then(|| result.artefacts.to_json()),
+                "strata": result
+                    .strata
+                    .iter()
+                    .map(|stratified| (stratified.name.to_string(), stratified.to_json()))
+                    .collect::<serde_json::Map<_, _>>(),
+                "confusion": result.confusion.to_json(),
+            })
+        })
+        .collect()
+}
+
+fn intervals_to_json(names: &[&str], intervals: &[Interval]) -> serde_json::Value {
+    names
+        .iter()
+        .zip(intervals)
+        .map(|(name, interval)| (name.to_string(), interval.to_json()))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
 }
 
 // fn print_results_by_input(results: Vec<ProgramResult>) {
@@ -360,8 +819,45 @@ fn print_results(results: &[ProgramResult]) {
 /*
 fn pychopper_grid_test(inputs: Vec<Input>, configs: Vec<config::PychopperConfig>) {
     iproduct!(inputs, configs)
-        .map(|(input, config)| (config::GenericProgramConfig{input: input.fastq.clone(), output: constants::OUTPUT_FILENAME.to_string()}, config, paf::parse(input.paf.clone())))
+        .map(|(input, config)| (config::GenericProgramConfig{input: input.fastq.clone(), output: constants::OUTPUT_FILENAME.to_string()}, config, truth::load(input.paf.clone())))
         .map(|(generic_config, pychopper_config, paf_reads)|
             pychopper::accuracy_timed_run_config(generic_config, pychopper_config, &paf_reads))
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(columns: &[(&str, &str)]) -> Vec<(String, String)> {
+        columns
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn csv_header_is_the_union_of_columns() {
+        let rows = vec![
+            row(&[("config", "a"), ("correct", "1"), ("n", "5")]),
+            row(&[
+                ("config", "b"),
+                ("correct", "2"),
+                ("length_short_reads", "3"),
+                ("n", "6"),
+            ]),
+            row(&[("config", "c"), ("n", "7")]),
+        ];
+
+        assert_eq!(
+            csv_lines(&rows),
+            vec![
+                "config,correct,length_short_reads,n",
+                "a,1,,5",
+                "b,2,3,6",
+                "c,,,7",
+            ]
+        );
+        assert!(csv_lines(&[]).is_empty());
+    }
+}
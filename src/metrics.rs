@@ -0,0 +1,650 @@
+use std::fmt;
+
+use itertools::Itertools;
+use serde_json::{json, Value};
+
+use crate::config::Bootstrap;
+
+/// Rates with a zero denominator are undefined, and kept as NaN. They're
+/// written as an empty CSV field, `null` in JSON (which `json!` does for any
+/// NaN) and `NA` in text.
+pub fn csv_value(value: f64) -> String {
+    if value.is_nan() {
+        String::new()
+    } else {
+        value.to_string()
+    }
+}
+
+/// `value` for text output, following the same rule as `csv_value`.
+pub fn text_value(value: f64) -> String {
+    if value.is_nan() {
+        "NA".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TruthLabel {
+    Forward,
+    Reverse,
+    Untruthed,
+}
+
+impl TruthLabel {
+    pub const ALL: [TruthLabel; 3] = [
+        TruthLabel::Forward,
+        TruthLabel::Reverse,
+        TruthLabel::Untruthed,
+    ];
+
+    pub fn from_strand(strand: Option<char>) -> TruthLabel {
+        match strand {
+            Some('+') => TruthLabel::Forward,
+            Some('-') => TruthLabel::Reverse,
+            _ => TruthLabel::Untruthed,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            TruthLabel::Forward => "fwd",
+            TruthLabel::Reverse => "rev",
+            TruthLabel::Untruthed => "untruthed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prediction {
+    Forward,
+    Reverse,
+    Ambiguous,
+    Missing,
+}
+
+impl Prediction {
+    pub const ALL: [Prediction; 4] = [
+        Prediction::Forward,
+        Prediction::Reverse,
+        Prediction::Ambiguous,
+        Prediction::Missing,
+    ];
+
+    pub fn from_strand(strand: char) -> Prediction {
+        match strand {
+            '+' => Prediction::Forward,
+            '-' => Prediction::Reverse,
+            _ => Prediction::Ambiguous,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Prediction::Forward => "fwd",
+            Prediction::Reverse => "rev",
+            Prediction::Ambiguous => "amb",
+            Prediction::Missing => "missing",
+        }
+    }
+}
+
+/// Read counts for each combination of truth strand and predicted strand.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfusionMatrix {
+    counts: [[u64; 4]; 3],
+}
+
+/// Precision, recall and F1 for calling one strand.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrandMetrics {
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+}
+
+/// Standard classification metrics, all over the reads with a known truth strand.
+///
+/// Recall and balanced accuracy count `?` and missing predictions as misses. MCC
+/// is only over the reads that were called `+` or `-`, so read it alongside the call rate.
+///
+/// A metric whose denominator is zero is undefined (see `csv_value`): precision
+/// when nothing was called that strand, recall when no read has it, and MCC when
+/// any row or column of the called 2x2 table is empty. F1 is undefined if either
+/// of its parts is, and 0 if both are 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassificationMetrics {
+    pub forward: StrandMetrics,
+    pub reverse: StrandMetrics,
+    pub mcc: f64,
+    pub balanced_accuracy: f64,
+    pub call_rate: f64,
+}
+
+impl ConfusionMatrix {
+    pub fn add(&mut self, truth: TruthLabel, prediction: Prediction) {
+        self.counts[truth as usize][prediction as usize] += 1;
+    }
+
+    pub fn merge(&mut self, other: &ConfusionMatrix) {
+        for (row, other) in self.counts.iter_mut().zip(&other.counts) {
+            for (count, other) in row.iter_mut().zip(other) {
+                *count += other;
+            }
+        }
+    }
+
+    pub fn get(&self, truth: TruthLabel, prediction: Prediction) -> u64 {
+        self.counts[truth as usize][prediction as usize]
+    }
+
+    fn row(&self, truth: TruthLabel) -> u64 {
+        self.counts[truth as usize].iter().sum()
+    }
+
+    pub fn metrics(&self) -> ClassificationMetrics {
+        use Prediction as P;
+        use TruthLabel as T;
+
+        let ratio = |a: u64, b: u64| a as f64 / b as f64;
+
+        let strand = |truth: TruthLabel, called: Prediction, other: TruthLabel| {
+            let true_positives = self.get(truth, called);
+            let precision = ratio(true_positives, true_positives + self.get(other, called));
+            let recall = ratio(true_positives, self.row(truth));
+            let f1 = match precision + recall {
+                0.0 => 0.0,
+                sum => 2.0 * precision * recall / sum,
+            };
+            StrandMetrics {
+                precision,
+                recall,
+                f1,
+            }
+        };
+        let forward = strand(T::Forward, P::Forward, T::Reverse);
+        let reverse = strand(T::Reverse, P::Reverse, T::Forward);
+
+        // MCC over the 2x2 table of called reads, with + as the positive class
+        let (tp, fn_, fp, tn) = (
+            self.get(T::Forward, P::Forward) as f64,
+            self.get(T::Forward, P::Reverse) as f64,
+            self.get(T::Reverse, P::Forward) as f64,
+            self.get(T::Reverse, P::Reverse) as f64,
+        );
+        let mcc = (tp * tn - fp * fn_) / ((tp + fp) * (tp + fn_) * (tn + fp) * (tn + fn_)).sqrt();
+
+        let truthed = self.row(T::Forward) + self.row(T::Reverse);
+        let called = tp + fn_ + fp + tn;
+
+        ClassificationMetrics {
+            balanced_accuracy: (forward.recall + reverse.recall) / 2.0,
+            call_rate: called / truthed as f64,
+            forward,
+            reverse,
+            mcc,
+        }
+    }
+
+    /// Each count, named `n_<truth>_<prediction>`.
+    pub fn columns(&self) -> Vec<(String, u64)> {
+        TruthLabel::ALL
+            .iter()
+            .flat_map(|truth| {
+                Prediction::ALL.iter().map(move |prediction| {
+                    (
+                        format!("n_{}_{}", truth.name(), prediction.name()),
+                        self.get(*truth, *prediction),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    pub fn to_json(&self) -> Value {
+        TruthLabel::ALL
+            .iter()
+            .map(|truth| {
+                (
+                    truth.name().to_string(),
+                    Prediction::ALL
+                        .iter()
+                        .map(|prediction| {
+                            (
+                                prediction.name().to_string(),
+                                json!(self.get(*truth, *prediction)),
+                            )
+                        })
+                        .collect(),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+}
+
+impl fmt::Display for ConfusionMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>12}", "truth\\pred")?;
+        for prediction in Prediction::ALL {
+            write!(f, "{:>10}", prediction.name())?;
+        }
+        for truth in TruthLabel::ALL {
+            write!(f, "\n{:>12}", truth.name())?;
+            for prediction in Prediction::ALL {
+                write!(f, "{:>10}", self.get(truth, prediction))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ClassificationMetrics {
+    /// Names of each metric, in the same order as `values`.
+    pub const NAMES: [&'static str; 9] = [
+        "fwd_precision",
+        "fwd_recall",
+        "fwd_f1",
+        "rev_precision",
+        "rev_recall",
+        "rev_f1",
+        "mcc",
+        "balanced_accuracy",
+        "call_rate",
+    ];
+
+    pub fn values(&self) -> [f64; 9] {
+        [
+            self.forward.precision,
+            self.forward.recall,
+            self.forward.f1,
+            self.reverse.precision,
+            self.reverse.recall,
+            self.reverse.f1,
+            self.mcc,
+            self.balanced_accuracy,
+            self.call_rate,
+        ]
+    }
+
+    pub fn to_json(&self) -> Value {
+        Self::NAMES
+            .iter()
+            .zip(self.values())
+            .map(|(name, value)| (name.to_string(), json!(value)))
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+}
+
+impl fmt::Display for ClassificationMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines = Self::NAMES
+            .iter()
+            .zip(self.values())
+            .map(|(name, value)| format!("{} {}", name.replace('_', " "), text_value(value)));
+        write!(f, "{}", lines.format("\n"))
+    }
+}
+
+/// z for a two-sided 95% interval.
+const Z_95: f64 = 1.959964;
+
+/// A 95% confidence interval.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    pub low: f64,
+    pub high: f64,
+}
+
+impl Interval {
+    const UNDEFINED: Interval = Interval {
+        low: f64::NAN,
+        high: f64::NAN,
+    };
+
+    /// The Wilson score interval for `successes` out of `total`. Undefined if
+    /// the count isn't actually a proportion of the total.
+    pub fn wilson(successes: u64, total: u64) -> Interval {
+        if total == 0 || successes > total {
+            return Interval::UNDEFINED;
+        }
+
+        let n = total as f64;
+        let p = successes as f64 / n;
+        let z2 = Z_95 * Z_95;
+        let centre = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+        let half_width = Z_95 / (1.0 + z2 / n) * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+
+        // the ends are exact at 0 and 1, rounding error aside
+        Interval {
+            low: if successes == 0 {
+                0.0
+            } else {
+                centre - half_width
+            },
+            high: if successes == total {
+                1.0
+            } else {
+                centre + half_width
+            },
+        }
+    }
+
+    /// The 2.5th and 97.5th percentiles, ignoring undefined values.
+    fn percentile(mut values: Vec<f64>) -> Interval {
+        values.retain(|value| !value.is_nan());
+        if values.is_empty() {
+            return Interval::UNDEFINED;
+        }
+
+        values.sort_by(f64::total_cmp);
+        let at = |q: f64| values[((values.len() - 1) as f64 * q).round() as usize];
+        Interval {
+            low: at(0.025),
+            high: at(0.975),
+        }
+    }
+
+    pub fn scale(&self, factor: f64) -> Interval {
+        Interval {
+            low: self.low * factor,
+            high: self.high * factor,
+        }
+    }
+
+    pub fn to_json(self) -> Value {
+        json!([self.low, self.high])
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}, {}]", text_value(self.low), text_value(self.high))
+    }
+}
+
+/// SplitMix64, so bootstrap results only depend on the seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn uniform(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    /// A draw from Binomial(n, p).
+    fn binomial(&mut self, n: u64, p: f64) -> u64 {
+        if p <= 0.0 || n == 0 {
+            return 0;
+        }
+        if p >= 1.0 {
+            return n;
+        }
+        if p > 0.5 {
+            return n - self.binomial(n, 1.0 - p);
+        }
+        if n as f64 * p < 10.0 {
+            self.binomial_inversion(n, p)
+        } else {
+            self.binomial_btrs(n, p)
+        }
+    }
+
+    /// Walk up the CDF, which takes about `n * p` steps.
+    fn binomial_inversion(&mut self, n: u64, p: f64) -> u64 {
+        let q = 1.0 - p;
+        let s = p / q;
+        let a = (n + 1) as f64 * s;
+        let mut r = q.powf(n as f64);
+        let mut u = self.uniform();
+        let mut x = 0;
+        while u > r && x < n {
+            u -= r;
+            x += 1;
+            r *= a / x as f64 - s;
+        }
+        x
+    }
+
+    /// Hörmann's transformed rejection with squeeze (BTRS), for `n * p >= 10`
+    /// and `p <= 0.5`. Takes a constant number of steps on average.
+    fn binomial_btrs(&mut self, n: u64, p: f64) -> u64 {
+        let (nf, q) = (n as f64, 1.0 - p);
+        let spq = (nf * p * q).sqrt();
+        let b = 1.15 + 2.53 * spq;
+        let a = -0.0873 + 0.0248 * b + 0.01 * p;
+        let c = nf * p + 0.5;
+        let v_r = 0.92 - 4.2 / b;
+        let alpha = (2.83 + 5.1 / b) * spq;
+        let lpq = (p / q).ln();
+        let m = ((nf + 1.0) * p).floor();
+        let h = ln_factorial(m) + ln_factorial(nf - m);
+
+        loop {
+            let u = self.uniform() - 0.5;
+            let v = self.uniform();
+            let us = 0.5 - u.abs();
+            let k = ((2.0 * a / us + b) * u + c).floor();
+            if k < 0.0 || k > nf {
+                continue;
+            }
+            if us >= 0.07 && v <= v_r {
+                return k as u64;
+            }
+
+            let v = (v * alpha / (a / (us * us) + b)).ln();
+            if v <= h - ln_factorial(k) - ln_factorial(nf - k) + (k - m) * lpq {
+                return k as u64;
+            }
+        }
+    }
+
+    /// A draw from the multinomial distribution of `n` items over cells with
+    /// the given weights, as one binomial per cell on what's left.
+    fn multinomial(&mut self, n: u64, weights: &[u64]) -> Vec<u64> {
+        let mut remaining = n;
+        let mut remaining_weight: u64 = weights.iter().sum();
+        weights
+            .iter()
+            .map(|weight| {
+                let count = match remaining_weight {
+                    0 => 0,
+                    total => self.binomial(remaining, *weight as f64 / total as f64),
+                };
+                remaining -= count;
+                remaining_weight -= weight;
+                count
+            })
+            .collect()
+    }
+}
+
+/// ln(k!) for a whole number `k`, exactly for small `k` and by Stirling's
+/// series otherwise.
+fn ln_factorial(k: f64) -> f64 {
+    if k < 10.0 {
+        return (2..=k as u64).map(|i| (i as f64).ln()).sum();
+    }
+    let k2 = k * k;
+    k * k.ln() - k + 0.5 * (2.0 * std::f64::consts::PI * k).ln() + 1.0 / (12.0 * k)
+        - 1.0 / (360.0 * k * k2)
+        + 1.0 / (1260.0 * k * k2 * k2)
+}
+
+impl ConfusionMatrix {
+    /// Bootstrap intervals for each of the classification metrics, in the order
+    /// of `ClassificationMetrics::NAMES`. Every scored read is one cell of the
+    /// matrix, so resampling reads with replacement is drawing the cell counts
+    /// from a multinomial weighted by the observed counts.
+    pub fn bootstrap(&self, bootstrap: &Bootstrap) -> Vec<Interval> {
+        let cells = self.counts.iter().flatten().copied().collect_vec();
+        let reads = cells.iter().sum();
+
+        let mut rng = Rng(bootstrap.seed);
+        let replicates = (0..bootstrap.replicates)
+            .map(|_| {
+                let mut sample = ConfusionMatrix::default();
+                for (cell, count) in rng.multinomial(reads, &cells).into_iter().enumerate() {
+                    sample.counts[cell / Prediction::ALL.len()][cell % Prediction::ALL.len()] =
+                        count;
+                }
+                sample.metrics().values()
+            })
+            .collect_vec();
+
+        (0..ClassificationMetrics::NAMES.len())
+            .map(|i| Interval::percentile(replicates.iter().map(|values| values[i]).collect()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matrix(cells: &[(TruthLabel, Prediction, u64)]) -> ConfusionMatrix {
+        let mut matrix = ConfusionMatrix::default();
+        for (truth, prediction, count) in cells {
+            for _ in 0..*count {
+                matrix.add(*truth, *prediction);
+            }
+        }
+        matrix
+    }
+
+    #[test]
+    fn computes_metrics() {
+        use Prediction as P;
+        use TruthLabel as T;
+
+        let metrics = matrix(&[
+            (T::Forward, P::Forward, 40),
+            (T::Forward, P::Reverse, 10),
+            (T::Forward, P::Ambiguous, 50),
+            (T::Reverse, P::Forward, 20),
+            (T::Reverse, P::Reverse, 30),
+            (T::Untruthed, P::Forward, 7),
+        ])
+        .metrics();
+
+        assert_eq!(metrics.forward.precision, 40.0 / 60.0);
+        assert_eq!(metrics.forward.recall, 0.4);
+        assert!((metrics.forward.f1 - 0.5).abs() < 1e-12);
+        assert_eq!(metrics.reverse.precision, 0.75);
+        assert_eq!(metrics.reverse.recall, 0.6);
+        assert!((metrics.mcc - 1000.0 / 6_000_000_f64.sqrt()).abs() < 1e-12);
+        assert_eq!(metrics.balanced_accuracy, 0.5);
+        assert_eq!(metrics.call_rate, 100.0 / 150.0);
+    }
+
+    #[test]
+    fn zero_denominators_are_undefined() {
+        use Prediction as P;
+        use TruthLabel as T;
+
+        // no reverse reads, and nothing called reverse
+        let metrics =
+            matrix(&[(T::Forward, P::Forward, 5), (T::Forward, P::Ambiguous, 5)]).metrics();
+        assert_eq!(metrics.forward.precision, 1.0);
+        assert!(metrics.reverse.precision.is_nan());
+        assert!(metrics.reverse.recall.is_nan());
+        assert!(metrics.reverse.f1.is_nan());
+        assert!(metrics.mcc.is_nan());
+        assert!(metrics.balanced_accuracy.is_nan());
+
+        // precision and recall both 0 gives an F1 of 0
+        let metrics = matrix(&[(T::Forward, P::Reverse, 5), (T::Reverse, P::Forward, 5)]).metrics();
+        assert_eq!(metrics.forward.f1, 0.0);
+
+        let metrics = ConfusionMatrix::default().metrics();
+        assert!(metrics.values().iter().all(|value| value.is_nan()));
+    }
+
+    #[test]
+    fn wilson_intervals() {
+        let interval = Interval::wilson(40, 100);
+        assert!((interval.low - 0.3094).abs() < 1e-4);
+        assert!((interval.high - 0.4980).abs() < 1e-4);
+
+        assert_eq!(Interval::wilson(0, 10).low, 0.0);
+        assert_eq!(Interval::wilson(10, 10).high, 1.0);
+        assert!(Interval::wilson(0, 0).low.is_nan());
+        assert!(Interval::wilson(11, 10).high.is_nan());
+    }
+
+    #[test]
+    fn binomial_draws_have_the_right_moments() {
+        let mut rng = Rng(7);
+        for (n, p) in [(20, 0.1), (1000, 0.3), (100000, 0.5), (1000, 0.97)] {
+            let draws = (0..4000).map(|_| rng.binomial(n, p) as f64).collect_vec();
+            let mean = draws.iter().sum::<f64>() / draws.len() as f64;
+            let variance =
+                draws.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / draws.len() as f64;
+
+            let (expected_mean, expected_variance) = (n as f64 * p, n as f64 * p * (1.0 - p));
+            assert!((mean - expected_mean).abs() < 4.0 * (expected_variance / 4000.0).sqrt());
+            assert!((variance / expected_variance - 1.0).abs() < 0.1);
+            assert!(draws.iter().all(|x| *x <= n as f64));
+        }
+    }
+
+    #[test]
+    fn multinomial_draws_keep_the_total() {
+        let mut rng = Rng(1);
+        let weights = [5, 0, 100, 3000, 0, 1];
+        for _ in 0..100 {
+            let counts = rng.multinomial(10000, &weights);
+            assert_eq!(counts.iter().sum::<u64>(), 10000);
+            assert_eq!((counts[1], counts[4]), (0, 0));
+        }
+        assert_eq!(rng.multinomial(10, &[0, 0]), vec![0, 0]);
+    }
+
+    #[test]
+    fn bootstrap_intervals_cover_the_estimate() {
+        use Prediction as P;
+        use TruthLabel as T;
+
+        let matrix = matrix(&[
+            (T::Forward, P::Forward, 400),
+            (T::Forward, P::Ambiguous, 600),
+            (T::Reverse, P::Reverse, 900),
+            (T::Reverse, P::Forward, 100),
+        ]);
+        let bootstrap = Bootstrap {
+            replicates: 2000,
+            seed: 3,
+        };
+        let intervals = matrix.bootstrap(&bootstrap);
+        assert_eq!(intervals, matrix.bootstrap(&bootstrap));
+
+        // close to the Wilson interval for forward recall, 400 of 1000
+        let recall = intervals[1];
+        let wilson = Interval::wilson(400, 1000);
+        assert!((recall.low - wilson.low).abs() < 0.01);
+        assert!((recall.high - wilson.high).abs() < 0.01);
+
+        for (value, interval) in matrix.metrics().values().iter().zip(&intervals) {
+            assert!(interval.low <= *value && *value <= interval.high);
+        }
+    }
+
+    #[test]
+    fn undefined_values_are_written_consistently() {
+        assert_eq!(csv_value(f64::NAN), "");
+        assert_eq!(csv_value(0.5), "0.5");
+        assert_eq!(text_value(f64::NAN), "NA");
+        assert_eq!(json!(f64::NAN), Value::Null);
+        assert_eq!(Interval::wilson(0, 0).to_json(), json!([null, null]));
+    }
+}
@@ -0,0 +1,103 @@
+use std::{fmt, process::Command, str::FromStr, time::Instant};
+
+use itertools::Itertools;
+
+use crate::{
+    command,
+    config::{
+        GenericProgramConfig, ProgramConfig, ProgramResult, ScoringConfig, SpecificProgramConfig,
+    },
+    constants,
+    header::NanoprepHeader,
+    input::InputReads,
+    paf::PafReads,
+};
+
+/// nanoprep's command line, from `--nanoprep-args`, with `{input}` and
+/// `{output}` standing in for the files of each run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NanoprepArgs(Vec<String>);
+
+impl NanoprepArgs {
+    fn fill(&self, input: &str, output: &str) -> Vec<String> {
+        self.0
+            .iter()
+            .map(|arg| arg.replace("{input}", input).replace("{output}", output))
+            .collect()
+    }
+}
+
+impl FromStr for NanoprepArgs {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let args = string.split_whitespace().map(String::from).collect_vec();
+        for placeholder in ["{input}", "{output}"] {
+            if !args.iter().any(|arg| arg.contains(placeholder)) {
+                return Err(format!("nanoprep arguments have no {}", placeholder));
+            }
+        }
+        Ok(NanoprepArgs(args))
+    }
+}
+
+impl fmt::Display for NanoprepArgs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.join(" "))
+    }
+}
+
+pub fn accuracy_timed_run_config(
+    generic_config: &GenericProgramConfig,
+    nanoprep_args: &NanoprepArgs,
+    paf_reads: &PafReads,
+    input_reads: &InputReads,
+    scoring_config: &ScoringConfig,
+) -> ProgramResult {
+    // run it and time it
+    let start = Instant::now();
+    run(generic_config, nanoprep_args);
+    let duration = start.elapsed().as_secs();
+
+    // score it from nanoprep's strand tag
+    ProgramResult::new(
+        ProgramConfig {
+            generic: generic_config.clone(),
+            specific: SpecificProgramConfig::Nanoprep,
+        },
+        duration,
+        paf_reads,
+        input_reads,
+        &NanoprepHeader,
+        scoring_config,
+    )
+}
+
+pub fn run(generic_config: &GenericProgramConfig, nanoprep_args: &NanoprepArgs) {
+    command::run(
+        "nanoprep",
+        Command::new(constants::NANOPREP_PATH)
+            .args(nanoprep_args.fill(&generic_config.input, &generic_config.output)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_in_each_runs_files() {
+        let args: NanoprepArgs = "--fastq {input} -o {output}.fq --strand".parse().unwrap();
+        assert_eq!(
+            args.fill("in.fq", "out"),
+            vec!["--fastq", "in.fq", "-o", "out.fq", "--strand"]
+        );
+    }
+
+    #[test]
+    fn rejects_args_without_both_files() {
+        assert!("{input}".parse::<NanoprepArgs>().is_err());
+        assert!("-o {output}".parse::<NanoprepArgs>().is_err());
+        assert!("".parse::<NanoprepArgs>().is_err());
+    }
+}
@@ -1,110 +1,719 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::hash::Hash;
-use std::io::{BufRead, BufReader};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::BufRead;
 
+use crate::annotation::Annotation;
+use crate::artefact::{is_chimeric, QueryBlock};
+use crate::config::{MissingTsPolicy, MultiMapperPolicy, TruthConfig};
+use crate::fastq;
+use crate::reader;
+
+#[allow(dead_code)]
 #[derive(Clone)]
 pub struct PafRead {
     pub name: String,
     pub strand: char,
 }
 
-#[derive(Clone)]
+/// An optional SAM-style `TAG:TYPE:VALUE` field from the end of a PAF line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PafTag {
+    pub name: String,
+    pub kind: char,
+    pub value: String,
+}
+
+impl PafTag {
+    pub fn from_field(field: &str) -> PafTag {
+        let mut parts = field.splitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(name), Some(kind), Some(value)) if kind.len() == 1 => PafTag {
+                name: name.to_string(),
+                kind: kind.chars().next().unwrap(),
+                value: value.to_string(),
+            },
+            _ => panic!("Malformed PAF tag: {}", field),
+        }
+    }
+}
+
+/// A full PAF alignment record, as written by minimap2.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PafRecord {
+    pub query_name: String,
+    pub query_length: u64,
+    pub query_start: u64,
+    pub query_end: u64,
+    pub strand: char,
+    pub target_name: String,
+    pub target_length: u64,
+    pub target_start: u64,
+    pub target_end: u64,
+    pub residue_matches: u64,
+    pub block_length: u64,
+    pub mapq: u8,
+    /// The read's primary alignment: `tp:A:P` in PAF, or neither secondary
+    /// nor supplementary in SAM/BAM.
+    pub primary: bool,
+    /// A SAM/BAM supplementary alignment, which only counts towards the
+    /// chimera check. PAF doesn't mark them.
+    pub supplementary: bool,
+    pub tags: Vec<PafTag>,
+}
+
+impl PafRecord {
+    pub fn from_paf_line(line: &str) -> PafRecord {
+        let fields = line.trim_end().split('\t').collect::<Vec<_>>();
+        if fields.len() < 12 {
+            panic!(
+                "PAF line has {} fields, expected at least 12: {}",
+                fields.len(),
+                line
+            );
+        }
+
+        let number = |i: usize| -> u64 {
+            fields[i]
+                .parse()
+                .unwrap_or_else(|_| panic!("Bad number in PAF column {}: {}", i + 1, line))
+        };
+
+        let strand = match fields[4] {
+            "+" => '+',
+            "-" => '-',
+            _ => panic!("Bad strand in PAF line: {}", line),
+        };
+
+        if number(3) < number(2) {
+            panic!("PAF query end is before its start: {}", line);
+        }
+
+        let tags = fields[12..]
+            .iter()
+            .map(|field| PafTag::from_field(field))
+            .collect::<Vec<_>>();
+        let primary = tags
+            .iter()
+            .any(|tag| tag.name == "tp" && tag.kind == 'A' && tag.value == "P");
+
+        PafRecord {
+            query_name: fields[0].to_string(),
+            query_length: number(1),
+            query_start: number(2),
+            query_end: number(3),
+            strand,
+            target_name: fields[5].to_string(),
+            target_length: number(6),
+            target_start: number(7),
+            target_end: number(8),
+            residue_matches: number(9),
+            block_length: number(10),
+            mapq: number(11).min(u8::MAX as u64) as u8,
+            primary,
+            supplementary: false,
+            tags,
+        }
+    }
+
+    /// Look up an optional tag, e.g. `tp` or `ts`.
+    pub fn tag(&self, name: &str) -> Option<&PafTag> {
+        self.tags.iter().find(|tag| tag.name == name)
+    }
+
+    /// The strand of the transcript this read came from, relative to the read,
+    /// according to minimap2's `ts:A` tag.
+    pub fn transcript_strand_from_tag(&self) -> Option<char> {
+        // ts is relative to the strand the read aligned to, so it's already relative to the read
+        match self.tag("ts") {
+            Some(PafTag {
+                kind: 'A', value, ..
+            }) if value == "+" || value == "-" => value.chars().next(),
+            _ => None,
+        }
+    }
+
+    /// Fraction of the alignment block made up of matching residues.
+    pub fn identity(&self) -> f64 {
+        if self.block_length == 0 {
+            return 0.0;
+        }
+        self.residue_matches as f64 / self.block_length as f64
+    }
+
+    /// Fraction of the query covered by the alignment.
+    pub fn query_coverage(&self) -> f64 {
+        if self.query_length == 0 {
+            return 0.0;
+        }
+        (self.query_end - self.query_start) as f64 / self.query_length as f64
+    }
+
+    /// The first truth filter this record fails, if any.
+    pub fn failed_filter(&self, config: &TruthConfig) -> Option<TruthFilter> {
+        if self.mapq < config.min_mapq {
+            Some(TruthFilter::Mapq)
+        } else if self.identity() < config.min_identity {
+            Some(TruthFilter::Identity)
+        } else if self.query_coverage() < config.min_query_coverage {
+            Some(TruthFilter::QueryCoverage)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TruthFilter {
+    Mapq,
+    Identity,
+    QueryCoverage,
+}
+
+/// How many reads each truth filter removed. A read is only counted as removed
+/// if none of its alignments passed, and is attributed to the filter that
+/// rejected its first alignment. Reads the multi-mapper policy could not
+/// resolve, and genome-aligned reads the annotation could not place on a
+/// single strand, are counted separately.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FilterCounts {
+    pub mapq: u64,
+    pub identity: u64,
+    pub query_coverage: u64,
+    pub multimapper: u64,
+    pub annotation: u64,
+    pub ts_tag: u64,
+}
+
+impl FilterCounts {
+    fn add(&mut self, filter: TruthFilter) {
+        match filter {
+            TruthFilter::Mapq => self.mapq += 1,
+            TruthFilter::Identity => self.identity += 1,
+            TruthFilter::QueryCoverage => self.query_coverage += 1,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.mapq
+            + self.identity
+            + self.query_coverage
+            + self.multimapper
+            + self.annotation
+            + self.ts_tag
+    }
+}
+
+impl fmt::Display for FilterCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "truth reads removed by filters: mapq {}, identity {}, query coverage {}, multi-mapper {}, annotation {}, ts tag {}",
+            self.mapq,
+            self.identity,
+            self.query_coverage,
+            self.multimapper,
+            self.annotation,
+            self.ts_tag
+        )
+    }
+}
+
+/// What's kept of a truth read's chosen alignment once its strand is known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TruthAlignment {
+    pub target_name: String,
+    pub query_length: u64,
+}
+
+impl TruthAlignment {
+    pub fn new(record: &PafRecord) -> TruthAlignment {
+        TruthAlignment {
+            target_name: record.target_name.clone(),
+            query_length: record.query_length,
+        }
+    }
+}
+
+/// A truth read with its name interned as a hash, for `--low-memory` scoring.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HashedRead {
+    pub name: u64,
+    pub strand: char,
+    pub chimeric: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct PafReads {
     pub map: HashMap<String, char>,
+    pub records: HashMap<String, TruthAlignment>,
+    /// Truth reads whose alignments say they're chimeric.
+    pub chimeric: HashSet<String>,
+    /// Under `--low-memory`, the truth reads sorted by name hash. The maps
+    /// above are then left empty.
+    pub hashed: Vec<HashedRead>,
     pub size: i32,
+    pub filtered: FilterCounts,
 }
 
 impl PafReads {
-    fn new() -> PafReads {
+    pub fn new() -> PafReads {
         PafReads {
             map: HashMap::new(),
+            records: HashMap::new(),
+            chimeric: HashSet::new(),
+            hashed: vec![],
             size: 0,
+            filtered: FilterCounts::default(),
         }
     }
 
-    pub fn insert(mut self, read: PafRead) {
-        self.map.insert(read.name, read.strand);
-        self.size += 1;
+    /// Add a read's alignment, with the truth strand it was given.
+    pub fn insert(&mut self, name: String, alignment: TruthAlignment, strand: char) {
+        self.map.insert(name.clone(), strand);
+        self.records.insert(name, alignment);
+        self.size = self.map.len() as i32;
     }
 
-    // pub fn insert(paf_reads: PafReads, read: PafRead) -> PafReads {
-    //     let thing = paf_reads.map.extend(
-    //         HashMap::from(paf_reads.map.));
-
-    //     PafReads {
-    //         map:
-    //         size: paf_reads.size + 1
-    //     }
-    // }
-
-    pub fn get(self, name: String) -> char {
-        match self.map.get(&name) {
+    pub fn get(&self, name: &str) -> char {
+        match self.map.get(name) {
             None => panic!("Name {} not in PAF!", name),
-            Some(strand) => strand.clone(),
+            Some(strand) => *strand,
         }
     }
 
-    pub fn size(self) -> i32 {
+    #[allow(dead_code)]
+    pub fn size(&self) -> i32 {
         self.size
     }
 }
 
+#[allow(dead_code)]
 impl PafRead {
-    pub fn from_paf_line(line: &Vec<u8>) -> PafRead {
-        let mut name: String = String::new();
-        let mut i: usize = 0;
-        while i < line.len() {
-            let c = line[i] as char;
-            if c != '\t' {
-                name.push(c);
-            } else {
-                break;
-            }
+    pub fn from_paf_line(line: &[u8]) -> PafRead {
+        PafRead::from(&PafRecord::from_paf_line(
+            std::str::from_utf8(line).expect("PAF line is not valid UTF-8"),
+        ))
+    }
+
+    fn _print(read: &PafRead) {
+        println!("{}, {}", read.name, read.strand);
+    }
+}
 
-            i += 1;
+impl From<&PafRecord> for PafRead {
+    fn from(record: &PafRecord) -> PafRead {
+        PafRead {
+            name: record.query_name.clone(),
+            strand: record.strand,
         }
+    }
+}
+
+/// Why an alignment couldn't be given a transcript strand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Unoriented {
+    Annotation,
+    TsTag,
+}
 
-        let mut strand: char = '?';
-        while i < line.len() {
-            let c = line[i] as char;
-            if c == '+' || c == '-' {
-                strand = c;
-                break;
+/// The strand of the transcript an alignment came from, relative to the
+/// read. That's from the annotation if there is one, then the `ts:A` tag if
+/// asked for, and otherwise just the alignment strand.
+fn orient(
+    record: &PafRecord,
+    config: &TruthConfig,
+    annotation: Option<&Annotation>,
+) -> Result<char, Unoriented> {
+    if let Some(annotation) = annotation {
+        return annotation
+            .transcript_strand(record, config)
+            .ok_or(Unoriented::Annotation);
+    }
+    if !config.ts_tag {
+        return Ok(record.strand);
+    }
+    match (record.transcript_strand_from_tag(), config.missing_ts) {
+        (Some(strand), _) => Ok(strand),
+        (None, MissingTsPolicy::Untruthed) => Ok('?'),
+        (None, MissingTsPolicy::Exclude) => Err(Unoriented::TsTag),
+    }
+}
+
+/// Choose the one alignment of a read to take the truth strand from,
+/// or None if the policy drops the read. Each alignment comes with its
+/// transcript strand, which is what conflicts are judged on.
+fn resolve_multimapper(
+    policy: MultiMapperPolicy,
+    records: Vec<(PafRecord, char)>,
+) -> Option<(PafRecord, char)> {
+    let strand_count = |strand: char| {
+        records
+            .iter()
+            .filter(|(_, oriented)| *oriented == strand)
+            .count()
+    };
+    let (forward, reverse) = (strand_count('+'), strand_count('-'));
+
+    let best_mapq = |strand: Option<char>| {
+        records
+            .iter()
+            .filter(|(_, oriented)| strand.is_none_or(|strand| *oriented == strand))
+            // max_by_key keeps the last maximum, so go in reverse to keep the first
+            .rev()
+            .max_by_key(|(record, _)| record.mapq)
+            .cloned()
+    };
+
+    match policy {
+        MultiMapperPolicy::First => records.into_iter().next(),
+        MultiMapperPolicy::Primary => records.into_iter().find(|(record, _)| record.primary),
+        MultiMapperPolicy::BestMapq => best_mapq(None),
+        MultiMapperPolicy::Majority if forward > reverse => best_mapq(Some('+')),
+        MultiMapperPolicy::Majority if reverse > forward => best_mapq(Some('-')),
+        MultiMapperPolicy::Majority => None,
+        MultiMapperPolicy::DropConflicting if forward > 0 && reverse > 0 => None,
+        MultiMapperPolicy::DropConflicting => records.into_iter().next(),
+    }
+}
+
+/// Every record in a PAF file, before any filtering.
+pub fn records(filename: &str) -> impl Iterator<Item = PafRecord> {
+    reader::open(filename)
+        .lines()
+        .map(|line| line.expect("Failed to read PAF line"))
+        .filter(|line| !line.is_empty())
+        .map(|line| PafRecord::from_paf_line(&line))
+}
+
+/// Settle on a truth strand for one read from all of its alignments. Each
+/// alignment that passes the truth filters is oriented, against the
+/// annotation if there is one, and then the multi-mapper policy picks one.
+/// SAM/BAM supplementary alignments only go towards the chimera check.
+/// Returns the chosen alignment, its strand and whether the read is chimeric,
+/// or None if the read is dropped, in which case it's counted in `filtered`.
+fn resolve_read(
+    records: Vec<PafRecord>,
+    config: &TruthConfig,
+    annotation: Option<&Annotation>,
+    filtered: &mut FilterCounts,
+) -> Option<(PafRecord, char, bool)> {
+    // chimeras show up in the low coverage alignments the filters may throw away
+    let blocks = records.iter().map(QueryBlock::new).collect::<Vec<_>>();
+
+    let mut rejected = None;
+    let mut unoriented = None;
+    let mut alignments = vec![];
+    for record in records.into_iter().filter(|record| !record.supplementary) {
+        if let Some(filter) = record.failed_filter(config) {
+            rejected.get_or_insert(filter);
+            continue;
+        }
+        match orient(&record, config, annotation) {
+            Ok(strand) => alignments.push((record, strand)),
+            Err(reason) => {
+                unoriented.get_or_insert(reason);
             }
+        }
+    }
 
-            i += 1;
+    // only count reads that lost every one of their alignments, and put
+    // them down to orientation if any alignment passed the filters
+    if alignments.is_empty() {
+        match (unoriented, rejected) {
+            (Some(Unoriented::Annotation), _) => filtered.annotation += 1,
+            (Some(Unoriented::TsTag), _) => filtered.ts_tag += 1,
+            (None, Some(filter)) => filtered.add(filter),
+            (None, None) => {}
         }
+        return None;
+    }
 
-        let read = PafRead { name, strand };
+    match resolve_multimapper(config.multimapper, alignments) {
+        Some((record, strand)) => Some((
+            record,
+            strand,
+            is_chimeric(&blocks, config.chimera_max_coverage),
+        )),
+        None => {
+            filtered.multimapper += 1;
+            None
+        }
+    }
+}
 
-        return read;
+/// Build the truth set from alignment records, whichever format they came
+/// from, and in whatever order.
+pub fn from_records(
+    records: impl Iterator<Item = PafRecord>,
+    config: &TruthConfig,
+    annotation: Option<&Annotation>,
+) -> PafReads {
+    let mut alignments: HashMap<String, Vec<PafRecord>> = HashMap::new();
+    for record in records {
+        alignments
+            .entry(record.query_name.clone())
+            .or_default()
+            .push(record);
     }
 
-    fn _print(read: &PafRead) {
-        println!("{}, {}", read.name, read.strand);
+    let mut reads = PafReads::new();
+    for records in alignments.into_values() {
+        let resolved = resolve_read(records, config, annotation, &mut reads.filtered);
+        if let Some((record, strand, chimeric)) = resolved {
+            if chimeric {
+                reads.chimeric.insert(record.query_name.clone());
+            }
+            let alignment = TruthAlignment::new(&record);
+            reads.insert(record.query_name, alignment, strand);
+        }
     }
+
+    reads
 }
 
-pub fn parse(filename: &str) -> PafReads {
-    let paf = BufReader::new(File::open(filename).expect("open failed"));
+/// Build the truth set for `--low-memory` scoring, holding nothing per read
+/// but a `HashedRead`. The records are streamed, so each read's alignments
+/// have to be next to each other, as minimap2 writes them. Name-sorted SAM
+/// and BAM are fine, coordinate-sorted aren't.
+pub fn hashed_from_records(
+    records: impl Iterator<Item = PafRecord>,
+    config: &TruthConfig,
+    annotation: Option<&Annotation>,
+) -> PafReads {
     let mut reads = PafReads::new();
+    let mut names = vec![];
 
-    for line in paf.lines() {
-        let read = PafRead::from_paf_line(&line.unwrap().as_bytes().to_vec());
-        reads.map.entry(read.name).or_insert(read.strand);
-        reads.size += 1;
-    }
+    let mut records = records.peekable();
+    while let Some(first) = records.next() {
+        let name = fastq::name_hash(&first.query_name);
+        let mut alignments = vec![first];
+        while let Some(record) =
+            records.next_if(|record| record.query_name == alignments[0].query_name)
+        {
+            alignments.push(record);
+        }
 
-    // let reads = paf.lines().into_iter()
-    //     .fold(PafReads::new(), |reads, line| -> PafReads {
-    //         let read = PafRead::from_paf_line(&line.unwrap().as_bytes().to_vec());
-    //         let mut map = reads.map.clone();
+        names.push(name);
+        if let Some((_, strand, chimeric)) =
+            resolve_read(alignments, config, annotation, &mut reads.filtered)
+        {
+            reads.hashed.push(HashedRead {
+                name,
+                strand,
+                chimeric,
+            });
+        }
+    }
 
-    //         reads.map.entry()
-    //         map.insert(read.name, read.strand);
-    //         PafReads { map, size: reads.size + 1 }
-    //     });
+    names.sort_unstable();
+    if names.windows(2).any(|pair| pair[0] == pair[1]) {
+        panic!("--low-memory needs each read's alignments next to each other in the truth file, sort it by read name first");
+    }
 
+    reads.hashed.sort_unstable_by_key(|read| read.name);
+    reads.size = reads.hashed.len() as i32;
     reads
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flags::Flags;
+
+    fn truth_config(flags: &[&str]) -> TruthConfig {
+        let args = flags.iter().map(|flag| flag.to_string()).collect();
+        TruthConfig::from_flags(&Flags::split_args(args).1)
+    }
+
+    fn record(name: &str, strand: char, mapq: u8, tags: &[&str]) -> PafRecord {
+        let mut line =
+            format!("{name}\t1000\t0\t1000\t{strand}\tchr1\t5000\t0\t1000\t1000\t1000\t{mapq}");
+        for tag in tags {
+            line.push('\t');
+            line.push_str(tag);
+        }
+        PafRecord::from_paf_line(&line)
+    }
+
+    #[test]
+    fn parses_tags() {
+        let primary = record("r1", '-', 60, &["tp:A:P", "cg:Z:10M2I", "de:f:0.01"]);
+        assert!(primary.primary);
+        assert_eq!(primary.tag("cg").unwrap().value, "10M2I");
+        assert_eq!(primary.tag("de").unwrap().kind, 'f');
+        assert!(primary.tag("ts").is_none());
+
+        assert!(!record("r1", '+', 60, &["tp:A:S"]).primary);
+        assert!(!record("r1", '+', 60, &[]).primary);
+
+        // values can contain colons
+        assert_eq!(PafTag::from_field("xx:Z:a:b").value, "a:b");
+    }
+
+    #[test]
+    fn filters_check_mapq_identity_and_coverage_in_turn() {
+        let config = truth_config(&[
+            "--min-mapq=10",
+            "--min-identity=0.9",
+            "--min-query-coverage=0.5",
+        ]);
+        let line = |mapq: u8, matches: u64, query_end: u64| {
+            PafRecord::from_paf_line(&format!(
+                "r1\t1000\t0\t{query_end}\t+\tchr1\t5000\t0\t1000\t{matches}\t1000\t{mapq}"
+            ))
+        };
+
+        assert_eq!(line(60, 950, 1000).failed_filter(&config), None);
+        assert_eq!(
+            line(5, 500, 100).failed_filter(&config),
+            Some(TruthFilter::Mapq)
+        );
+        assert_eq!(
+            line(60, 500, 100).failed_filter(&config),
+            Some(TruthFilter::Identity)
+        );
+        assert_eq!(
+            line(60, 950, 400).failed_filter(&config),
+            Some(TruthFilter::QueryCoverage)
+        );
+    }
+
+    #[test]
+    fn ts_tag_gives_strand_relative_to_transcript() {
+        // (alignment strand, ts, read orientation)
+        let cases = [
+            // read and transcript both on genome +
+            ('+', "+", '+'),
+            // read on genome -, transcript on genome -
+            ('-', "+", '+'),
+            // read on genome +, transcript on genome -
+            ('+', "-", '-'),
+            // read on genome -, transcript on genome +
+            ('-', "-", '-'),
+        ];
+        for (strand, ts, expected) in cases {
+            let record = record("r1", strand, 60, &[&format!("ts:A:{ts}")]);
+            assert_eq!(record.transcript_strand_from_tag(), Some(expected));
+        }
+
+        assert_eq!(
+            record("r1", '+', 60, &[]).transcript_strand_from_tag(),
+            None
+        );
+        assert_eq!(
+            record("r1", '+', 60, &["ts:A:?"]).transcript_strand_from_tag(),
+            None
+        );
+        assert_eq!(
+            record("r1", '+', 60, &["ts:Z:+"]).transcript_strand_from_tag(),
+            None
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Malformed PAF tag")]
+    fn rejects_malformed_tags() {
+        PafTag::from_field("tp:AP");
+    }
+
+    #[test]
+    #[should_panic(expected = "query end is before its start")]
+    fn rejects_backwards_query_coordinates() {
+        PafRecord::from_paf_line("r1\t1000\t500\t400\t+\tchr1\t5000\t0\t100\t100\t100\t60");
+    }
+
+    #[test]
+    fn primary_policy_uses_primary_alignment() {
+        let records = vec![
+            record("r1", '+', 60, &["tp:A:S"]),
+            record("r1", '-', 10, &["tp:A:P"]),
+        ];
+        let config = truth_config(&["--multimapper=primary"]);
+
+        let reads = from_records(records.into_iter(), &config, None);
+        assert_eq!(reads.map["r1"], '-');
+    }
+
+    #[test]
+    fn majority_is_judged_on_transcript_strand() {
+        // two genomic + alignments against one -, but two of the three are
+        // antisense to their transcripts
+        let records = vec![
+            record("r1", '+', 10, &["ts:A:-"]),
+            record("r1", '-', 10, &["ts:A:-"]),
+            record("r1", '+', 60, &["ts:A:+"]),
+        ];
+        let config = truth_config(&["--ts-tag", "--multimapper=majority"]);
+
+        let reads = from_records(records.into_iter(), &config, None);
+        assert_eq!(reads.map["r1"], '-');
+    }
+
+    #[test]
+    fn drop_conflicting_ignores_genomic_strand() {
+        // opposite genomic strands, but the same transcript strand
+        let records = vec![
+            record("r1", '+', 60, &["ts:A:+"]),
+            record("r1", '-', 60, &["ts:A:+"]),
+        ];
+        let config = truth_config(&["--ts-tag", "--multimapper=drop-conflicting"]);
+
+        let reads = from_records(records.into_iter(), &config, None);
+        assert_eq!(reads.map["r1"], '+');
+        assert_eq!(reads.filtered.multimapper, 0);
+    }
+
+    #[test]
+    fn unoriented_reads_are_counted_once() {
+        let records = vec![record("r1", '+', 60, &[]), record("r1", '-', 60, &[])];
+        let config = truth_config(&["--ts-tag"]);
+
+        let reads = from_records(records.into_iter(), &config, None);
+        assert!(reads.map.is_empty());
+        assert_eq!(reads.filtered.ts_tag, 1);
+    }
+
+    #[test]
+    fn hashed_truth_matches_named_truth() {
+        let records = vec![
+            record("r1", '+', 60, &[]),
+            record("r2", '-', 60, &[]),
+            record("r3", '+', 0, &[]),
+            // two halves of the read on opposite strands
+            PafRecord::from_paf_line("r4\t1000\t0\t400\t+\tchr1\t5000\t0\t400\t400\t400\t60"),
+            PafRecord::from_paf_line("r4\t1000\t500\t1000\t-\tchr1\t5000\t0\t500\t500\t500\t60"),
+        ];
+        let config = truth_config(&["--min-mapq=10"]);
+
+        let named = from_records(records.clone().into_iter(), &config, None);
+        let hashed = hashed_from_records(records.into_iter(), &config, None);
+        assert!(named.chimeric.contains("r4"));
+
+        let mut expected = named
+            .map
+            .iter()
+            .map(|(name, strand)| HashedRead {
+                name: fastq::name_hash(name),
+                strand: *strand,
+                chimeric: named.chimeric.contains(name),
+            })
+            .collect::<Vec<_>>();
+        expected.sort_unstable_by_key(|read| read.name);
+
+        assert_eq!(hashed.hashed, expected);
+        assert_eq!(hashed.filtered, named.filtered);
+        assert_eq!(hashed.filtered.mapq, 1);
+        assert!(hashed.map.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "next to each other")]
+    fn hashed_truth_needs_grouped_alignments() {
+        let records = vec![
+            record("r1", '+', 60, &[]),
+            record("r2", '+', 60, &[]),
+            record("r1", '+', 60, &[]),
+        ];
+        hashed_from_records(records.into_iter(), &truth_config(&[]), None);
+    }
+}
@@ -0,0 +1,238 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::config::Protocol;
+use crate::sequence::reverse_complement;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Primer {
+    Tso,
+    Rtp,
+}
+
+/// Where a primer, or its reverse complement, was found in a read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrimerHit {
+    pub primer: Primer,
+    /// `+` if the read has the primer as given, `-` if it has the reverse complement.
+    pub strand: char,
+    pub start: usize,
+    pub end: usize,
+    pub edit_distance: usize,
+}
+
+impl fmt::Display for PrimerHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?}{} {}-{} ({} edits)",
+            self.primer, self.strand, self.start, self.end, self.edit_distance
+        )
+    }
+}
+
+/// Which primers turned up anywhere in a read, in either orientation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrimerConfiguration {
+    Both,
+    TsoOnly,
+    RtpOnly,
+    Neither,
+}
+
+impl PrimerConfiguration {
+    pub const ALL: [PrimerConfiguration; 4] = [
+        PrimerConfiguration::Both,
+        PrimerConfiguration::TsoOnly,
+        PrimerConfiguration::RtpOnly,
+        PrimerConfiguration::Neither,
+    ];
+
+    pub fn new(hits: &[PrimerHit]) -> PrimerConfiguration {
+        let found = |primer| hits.iter().any(|hit| hit.primer == primer);
+        match (found(Primer::Tso), found(Primer::Rtp)) {
+            (true, true) => PrimerConfiguration::Both,
+            (true, false) => PrimerConfiguration::TsoOnly,
+            (false, true) => PrimerConfiguration::RtpOnly,
+            (false, false) => PrimerConfiguration::Neither,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PrimerConfiguration::Both => "both",
+            PrimerConfiguration::TsoOnly => "tso-only",
+            PrimerConfiguration::RtpOnly => "rtp-only",
+            PrimerConfiguration::Neither => "none",
+        }
+    }
+}
+
+/// How hard to look for primers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrimerSearch {
+    /// The most edits a match can have.
+    pub max_edit_distance: usize,
+    /// How far in from each end of the read to look, or 0 to search the whole read.
+    pub window: usize,
+}
+
+/// The best approximate match of `pattern` anywhere in `text`, as
+/// (start, end, edit distance). Sellers' algorithm: edit distance where the
+/// match can start and end anywhere in the text for free.
+fn best_match(pattern: &[u8], text: &[u8]) -> Option<(usize, usize, usize)> {
+    // each cell holds the distance and where in the text its alignment started
+    let mut column: Vec<(usize, usize)> = (0..=pattern.len()).map(|i| (i, 0)).collect();
+    let mut best: Option<(usize, usize, usize)> = None;
+
+    for (j, base) in text.iter().enumerate() {
+        let mut diagonal = column[0];
+        column[0] = (0, j + 1);
+        for (i, expected) in pattern.iter().enumerate() {
+            let substitution = (
+                diagonal.0 + !base.eq_ignore_ascii_case(expected) as usize,
+                diagonal.1,
+            );
+            let deletion = (column[i + 1].0 + 1, column[i + 1].1);
+            let insertion = (column[i].0 + 1, column[i].1);
+
+            diagonal = column[i + 1];
+            column[i + 1] = [substitution, deletion, insertion]
+                .into_iter()
+                .min_by_key(|(distance, _)| *distance)
+                .unwrap();
+        }
+
+        let (distance, start) = column[pattern.len()];
+        if best.is_none_or(|(_, _, best)| distance < best) {
+            best = Some((start, j + 1, distance));
+        }
+    }
+
+    best
+}
+
+/// Look for the protocol's primers and their reverse complements in one read,
+/// keeping the best match of each that's close enough.
+pub fn search_read(seq: &[u8], protocol: &Protocol, search: &PrimerSearch) -> Vec<PrimerHit> {
+    // the regions to look in, as offsets into the read
+    let regions: Vec<Range<usize>> = if search.window == 0 || seq.len() <= search.window * 2 {
+        std::iter::once(0..seq.len()).collect()
+    } else {
+        vec![0..search.window, seq.len() - search.window..seq.len()]
+    };
+
+    let mut hits = vec![];
+    for (primer, sequence) in [(Primer::Tso, protocol.tso()), (Primer::Rtp, protocol.rtp())] {
+        let sequence = sequence.as_bytes();
+        for (strand, pattern) in [
+            ('+', sequence.to_vec()),
+            ('-', reverse_complement(sequence)),
+        ] {
+            let best = regions
+                .iter()
+                .filter_map(|region| {
+                    best_match(&pattern, &seq[region.clone()]).map(|(start, end, distance)| {
+                        (region.start + start, region.start + end, distance)
+                    })
+                })
+                .min_by_key(|(_, _, distance)| *distance);
+
+            if let Some((start, end, edit_distance)) = best {
+                if edit_distance <= search.max_edit_distance {
+                    hits.push(PrimerHit {
+                        primer,
+                        strand,
+                        start,
+                        end,
+                        edit_distance,
+                    });
+                }
+            }
+        }
+    }
+
+    hits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEARCH: PrimerSearch = PrimerSearch {
+        max_edit_distance: 6,
+        window: 200,
+    };
+
+    /// A stretch of sequence unlike either PCB111 primer.
+    fn filler(length: usize) -> Vec<u8> {
+        b"GACA".iter().copied().cycle().take(length).collect()
+    }
+
+    #[test]
+    fn finds_exact_matches() {
+        assert_eq!(best_match(b"ACGT", b"TTACGTTT"), Some((2, 6, 0)));
+        assert_eq!(best_match(b"ACGT", b"ttacgttt"), Some((2, 6, 0)));
+        assert_eq!(best_match(b"ACGT", b""), None);
+    }
+
+    #[test]
+    fn counts_edits() {
+        // substitution
+        assert_eq!(best_match(b"ACGTAC", b"GGACCTACGG").unwrap().2, 1);
+        // insertion in the text
+        assert_eq!(best_match(b"ACGTAC", b"GGACGTTACGG").unwrap().2, 1);
+        // deletion from the text
+        assert_eq!(best_match(b"ACGTAC", b"GGACTACGG").unwrap().2, 1);
+        // overhanging the end of the text costs one per missing base
+        assert_eq!(best_match(b"ACGTAC", b"GGGGACGT").unwrap().2, 2);
+    }
+
+    #[test]
+    fn finds_primers_in_both_orientations() {
+        let protocol = Protocol::PCB111;
+        let mut read = protocol.tso().as_bytes().to_vec();
+        read.extend(filler(1000));
+        read.extend(reverse_complement(protocol.rtp().as_bytes()));
+
+        let hits = search_read(&read, &protocol, &SEARCH);
+        let found = hits
+            .iter()
+            .map(|hit| (hit.primer, hit.strand, hit.edit_distance))
+            .collect::<Vec<_>>();
+        assert!(found.contains(&(Primer::Tso, '+', 0)));
+        assert!(found.contains(&(Primer::Rtp, '-', 0)));
+        assert_eq!(PrimerConfiguration::new(&hits), PrimerConfiguration::Both);
+
+        let rtp = hits
+            .iter()
+            .find(|hit| hit.primer == Primer::Rtp && hit.edit_distance == 0)
+            .unwrap();
+        assert_eq!(
+            (rtp.start, rtp.end),
+            (read.len() - protocol.rtp().len(), read.len())
+        );
+    }
+
+    #[test]
+    fn only_searches_the_read_ends() {
+        let protocol = Protocol::PCB111;
+        let mut read = filler(500);
+        read.extend(protocol.tso().as_bytes());
+        read.extend(filler(500));
+
+        assert_eq!(
+            PrimerConfiguration::new(&search_read(&read, &protocol, &SEARCH)),
+            PrimerConfiguration::Neither
+        );
+
+        let whole_read = PrimerSearch {
+            window: 0,
+            ..SEARCH
+        };
+        assert_eq!(
+            PrimerConfiguration::new(&search_read(&read, &protocol, &whole_read)),
+            PrimerConfiguration::TsoOnly
+        );
+    }
+}
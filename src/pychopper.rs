@@ -1,21 +1,27 @@
-use std::{collections::HashMap, fs::remove_file, process::Command, time::Instant};
+use std::{process::Command, time::Instant};
 
 use crate::{
+    command,
     config::{
-        GenericProgramConfig, ProgramConfig, ProgramResult, PychopperConfig, SpecificProgramConfig,
+        GenericProgramConfig, ProgramConfig, ProgramResult, PychopperConfig, ScoringConfig,
+        SpecificProgramConfig,
     },
-    constants, fastq,
-    paf::{PafRead, PafReads},
+    constants,
+    header::PychopperHeader,
+    input::InputReads,
+    paf::PafReads,
 };
 
 pub fn accuracy_timed_run_config(
     generic_config: &GenericProgramConfig,
     specific_config: &SpecificProgramConfig,
     paf_reads: &PafReads,
+    input_reads: &InputReads,
+    scoring_config: &ScoringConfig,
 ) -> ProgramResult {
     // get the backend argument string
     let backend_string = match specific_config.clone() {
-        SpecificProgramConfig::Restrander(_) => panic!("aaa"),
+        SpecificProgramConfig::Restrander(_) | SpecificProgramConfig::Nanoprep => panic!("aaa"),
         SpecificProgramConfig::Pychopper(PychopperConfig {
             backend,
             protocol: _,
@@ -26,7 +32,9 @@ pub fn accuracy_timed_run_config(
     };
 
     let protocol_string = match specific_config.clone() {
-        SpecificProgramConfig::Restrander(_) => panic!("wrong config provided!"),
+        SpecificProgramConfig::Restrander(_) | SpecificProgramConfig::Nanoprep => {
+            panic!("wrong config provided!")
+        }
         SpecificProgramConfig::Pychopper(PychopperConfig {
             backend: _,
             protocol,
@@ -38,53 +46,44 @@ pub fn accuracy_timed_run_config(
 
     // run it and time it
     let start = Instant::now();
-    let command = format!(
-        "{} run pychopper {} {} {}",
-        constants::CONDA_PATH,
-        backend_string,
-        generic_config.clone().input,
-        generic_config.clone().output
+    command::run(
+        "pychopper",
+        Command::new(constants::CONDA_PATH)
+            .arg("run")
+            .arg("pychopper")
+            .args(["-m", backend_string])
+            .args(["-k", protocol_string])
+            .arg(generic_config.clone().input)
+            .arg(generic_config.clone().output),
     );
-    Command::new(constants::CONDA_PATH)
-        .arg("run")
-        .arg("pychopper")
-        .args(&["-m", backend_string])
-        .args(&["-k", protocol_string])
-        .arg(generic_config.clone().input)
-        .arg(generic_config.clone().output)
-        // Command::new(Con)
-        .spawn()
-        .expect("pychopper failed to start")
-        .wait()
-        .expect("pychopper failed to terminate");
     let duration = start.elapsed().as_secs();
 
-    // determine the accuracy
-    let accuracy = fastq::parse(&generic_config.clone().output, &paf_reads, true);
-
     // delete the file if necessary
     // remove_file(generic_config.clone().output)
     //     .expect("Couldn't delete file!");
 
-    // construct a program result at the end
-    ProgramResult {
-        config: ProgramConfig {
+    // score it and build the result
+    ProgramResult::new(
+        ProgramConfig {
             generic: generic_config.clone(),
             specific: specific_config.clone(),
         },
         duration,
-        accuracy,
-    }
+        paf_reads,
+        input_reads,
+        &PychopperHeader,
+        scoring_config,
+    )
 }
 
 pub fn run(
     generic_config: &GenericProgramConfig,
     specific_config: &SpecificProgramConfig,
-    paf_reads: &PafReads,
+    _paf_reads: &PafReads,
 ) {
     // get the backend argument string
     let backend_string = match specific_config.clone() {
-        SpecificProgramConfig::Restrander(_) => panic!("aaa"),
+        SpecificProgramConfig::Restrander(_) | SpecificProgramConfig::Nanoprep => panic!("aaa"),
         SpecificProgramConfig::Pychopper(PychopperConfig {
             backend,
             protocol: _,
@@ -95,7 +94,9 @@ pub fn run(
     };
 
     let protocol_string = match specific_config.clone() {
-        SpecificProgramConfig::Restrander(_) => panic!("wrong config provided!"),
+        SpecificProgramConfig::Restrander(_) | SpecificProgramConfig::Nanoprep => {
+            panic!("wrong config provided!")
+        }
         SpecificProgramConfig::Pychopper(PychopperConfig {
             backend: _,
             protocol,
@@ -105,15 +106,14 @@ pub fn run(
         },
     };
 
-    Command::new(constants::CONDA_PATH)
-        .arg("run")
-        .arg("pychopper")
-        .args(&["-m", backend_string])
-        .args(&["-k", protocol_string])
-        .arg(generic_config.clone().input)
-        .arg(generic_config.clone().output)
-        .spawn()
-        .expect("pychopper failed to start")
-        .wait()
-        .expect("pychopper failed to terminate");
+    command::run(
+        "pychopper",
+        Command::new(constants::CONDA_PATH)
+            .arg("run")
+            .arg("pychopper")
+            .args(["-m", backend_string])
+            .args(["-k", protocol_string])
+            .arg(generic_config.clone().input)
+            .arg(generic_config.clone().output),
+    );
 }
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use flate2::read::MultiGzDecoder;
+use seq_io::fastq::{Reader, RecordSet, RefRecord};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Open a file for buffered reading, transparently decompressing it if it
+/// starts with the gzip magic bytes. bgzip files are multi-member gzip, so
+/// they're handled the same way.
+pub fn open(filename: &str) -> Box<dyn BufRead> {
+    let mut file = BufReader::new(
+        File::open(filename).unwrap_or_else(|e| panic!("Failed to open {}: {}", filename, e)),
+    );
+
+    let is_gzip = file
+        .fill_buf()
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", filename, e))
+        .starts_with(&GZIP_MAGIC);
+
+    if is_gzip {
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    } else {
+        Box::new(file)
+    }
+}
+
+/// Run `work` on every record of a FASTQ file and hand the results to `func`
+/// in file order. With more than one thread, one reads batches of records
+/// while the rest run `work` on them, so the results are the same whatever
+/// the thread count.
+pub fn map_fastq<O: Send>(
+    filename: &str,
+    threads: usize,
+    work: impl Fn(RefRecord) -> O + Sync,
+    mut func: impl FnMut(O),
+) {
+    if threads <= 1 {
+        let mut reader = Reader::new(open(filename));
+        while let Some(record) = reader.next() {
+            func(work(record.expect("Error reading record")));
+        }
+        return;
+    }
+
+    // batches go out with their position in the file, and come back in any order
+    let (batch_send, batch_recv) = mpsc::sync_channel::<(usize, RecordSet)>(threads * 2);
+    // shared so that if every worker stops, the reader does too
+    let batch_recv = Arc::new(Mutex::new(batch_recv));
+    let (done_send, done_recv) = mpsc::channel::<(usize, Vec<O>)>();
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            let mut reader = Reader::new(open(filename));
+            for index in 0.. {
+                let mut batch = RecordSet::default();
+                match reader.read_record_set(&mut batch) {
+                    Some(result) => result.expect("Error reading record"),
+                    None => break,
+                }
+                if batch_send.send((index, batch)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        for _ in 0..threads - 1 {
+            let (batch_recv, done_send, work) = (batch_recv.clone(), done_send.clone(), &work);
+            scope.spawn(move || loop {
+                let next = batch_recv.lock().expect("Batch queue poisoned").recv();
+                let Ok((index, batch)) = next else {
+                    break;
+                };
+                let results = batch.into_iter().map(work).collect();
+                if done_send.send((index, results)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop((batch_recv, done_send));
+
+        let mut pending: HashMap<usize, Vec<O>> = HashMap::new();
+        let mut next = 0;
+        for (index, results) in done_recv {
+            pending.insert(index, results);
+            while let Some(results) = pending.remove(&next) {
+                results.into_iter().for_each(&mut func);
+                next += 1;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use seq_io::fastq::Record;
+    use std::io::{Read, Write};
+
+    fn gzip(text: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn opens_plain_and_multi_member_gzip_files() {
+        let path = std::env::temp_dir().join(format!(
+            "restrander-testing-rig-gzip-{}",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+
+        // bgzip writes a series of gzip members, which read as one stream
+        let members = [gzip("first line\n"), gzip("second line\n")].concat();
+        for contents in [b"first line\nsecond line\n".to_vec(), members] {
+            std::fs::write(path, contents).unwrap();
+            let mut text = String::new();
+            open(path).read_to_string(&mut text).unwrap();
+            assert_eq!(text, "first line\nsecond line\n");
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn map_fastq_keeps_file_order() {
+        let path = std::env::temp_dir().join(format!(
+            "restrander-testing-rig-reader-{}.fq",
+            std::process::id()
+        ));
+        let names = (0..20000).map(|i| format!("read{i}")).collect::<Vec<_>>();
+        let fastq = names
+            .iter()
+            .map(|name| format!("@{name}\nACGT\n+\nIIII\n"))
+            .collect::<String>();
+        std::fs::write(&path, fastq).unwrap();
+
+        for threads in [1, 2, 5] {
+            let mut seen = vec![];
+            map_fastq(
+                path.to_str().unwrap(),
+                threads,
+                |record| record.id().unwrap().to_string(),
+                |name| seen.push(name),
+            );
+            assert_eq!(seen, names);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
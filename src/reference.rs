@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+
+use itertools::Itertools;
+
+use crate::config::ScoringConfig;
+use crate::fastq::{AccuracyResult, CategorisedReads};
+use crate::metrics::csv_value;
+use crate::paf::PafReads;
+use crate::reader;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Transcript,
+    Gene,
+}
+
+impl Level {
+    fn name(&self) -> &'static str {
+        match self {
+            Level::Transcript => "transcript",
+            Level::Gene => "gene",
+        }
+    }
+}
+
+/// The accuracy over the reads aligned to one reference sequence, or to any
+/// transcript of one gene.
+#[derive(Debug, Clone)]
+pub struct ReferenceResult {
+    pub level: Level,
+    pub name: String,
+    pub reads: u64,
+    pub correct: u64,
+    pub incorrect: u64,
+    pub ambiguous: u64,
+    pub accuracy: AccuracyResult,
+}
+
+/// Load a transcript to gene table: tab-separated, transcript first and gene
+/// second, with `#` comment lines and any further columns ignored.
+pub fn load_gene_table(filename: &str) -> HashMap<String, String> {
+    reader::open(filename)
+        .lines()
+        .map(|line| line.expect("Failed to read gene table line"))
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut fields = line.split('\t');
+            match (fields.next(), fields.next()) {
+                (Some(transcript), Some(gene)) => (transcript.to_string(), gene.to_string()),
+                _ => panic!("Gene table line has fewer than 2 fields: {}", line),
+            }
+        })
+        .collect()
+}
+
+fn aggregate(
+    level: Level,
+    names: &HashMap<String, String>,
+    categorised: &CategorisedReads,
+    scoring_config: &ScoringConfig,
+) -> Vec<ReferenceResult> {
+    let groups = names.values().unique().cloned().collect_vec();
+    let index: HashMap<&str, usize> = groups
+        .iter()
+        .enumerate()
+        .map(|(i, group)| (group.as_str(), i))
+        .collect();
+
+    let counts = categorised.count_by(groups.len(), |read| {
+        names.get(read).map(|group| index[group.as_str()])
+    });
+
+    groups
+        .into_iter()
+        .zip(counts)
+        .map(|(name, exact)| ReferenceResult {
+            level,
+            name,
+            reads: exact.reads(),
+            correct: exact.correct,
+            incorrect: exact.incorrect,
+            ambiguous: exact.ambiguous,
+            accuracy: exact.accuracy(scoring_config),
+        })
+        .collect()
+}
+
+/// Aggregate the scored reads by the reference each truth read aligned to,
+/// and by gene if there's a gene table. References with no gene in the table
+/// are left out of the gene level.
+pub fn aggregate_references(
+    paf_reads: &PafReads,
+    categorised: &CategorisedReads,
+    scoring_config: &ScoringConfig,
+) -> Vec<ReferenceResult> {
+    let transcripts: HashMap<String, String> = paf_reads
+        .records
+        .iter()
+        .map(|(name, record)| (name.clone(), record.target_name.clone()))
+        .collect();
+
+    let mut results = aggregate(Level::Transcript, &transcripts, categorised, scoring_config);
+
+    if let Some(gene_table) = &scoring_config.gene_table {
+        let gene_table = load_gene_table(gene_table);
+        let genes = transcripts
+            .into_iter()
+            .filter_map(|(read, transcript)| Some((read, gene_table.get(&transcript)?.clone())))
+            .collect();
+        results.extend(aggregate(Level::Gene, &genes, categorised, scoring_config));
+    }
+
+    results
+}
+
+/// Write each config's references as a TSV, transcripts then genes, each
+/// with the worst incorrect-strand rate first.
+pub fn save_tsv(path: &str, results: &[(String, &[ReferenceResult])]) {
+    let mut file = BufWriter::new(
+        File::create(path).unwrap_or_else(|_| panic!("Failed to create file {}", path)),
+    );
+
+    writeln!(
+        file,
+        "config\tlevel\tname\treads\tcorrect\tincorrect\tambiguous\tincorrect_percent"
+    )
+    .expect("Failed to write to file!");
+
+    for (config, references) in results {
+        // references with only untruthed reads have no rate, so they go last
+        let rate = |reference: &ReferenceResult| match reference.accuracy.incorrect {
+            rate if rate.is_nan() => -1.0,
+            rate => rate,
+        };
+        let ranked = references.iter().sorted_by(|a, b| {
+            (a.level as u8)
+                .cmp(&(b.level as u8))
+                .then(rate(b).total_cmp(&rate(a)))
+                .then(b.reads.cmp(&a.reads))
+                .then(a.name.cmp(&b.name))
+        });
+        for reference in ranked {
+            writeln!(
+                file,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                config,
+                reference.level.name(),
+                reference.name,
+                reference.reads,
+                reference.correct,
+                reference.incorrect,
+                reference.ambiguous,
+                csv_value(reference.accuracy.incorrect)
+            )
+            .expect("Failed to write to file!");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flags::Flags;
+    use crate::paf::TruthAlignment;
+    use std::collections::HashSet;
+
+    fn names(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn aggregates_and_ranks_references() {
+        let dir = std::env::temp_dir();
+        let genes = dir.join(format!(
+            "restrander-testing-rig-genes-{}.tsv",
+            std::process::id()
+        ));
+        let tsv = dir.join(format!(
+            "restrander-testing-rig-references-{}.tsv",
+            std::process::id()
+        ));
+        std::fs::write(&genes, "# transcript\tgene\ntx1\tg1\ntx2\tg1\textra\n").unwrap();
+
+        let mut paf_reads = PafReads::new();
+        for (name, target_name) in [("r1", "tx1"), ("r2", "tx1"), ("r3", "tx2"), ("r4", "tx3")] {
+            let alignment = TruthAlignment {
+                target_name: target_name.to_string(),
+                query_length: 1000,
+            };
+            paf_reads.insert(name.to_string(), alignment, '+');
+        }
+        let categorised = CategorisedReads {
+            correct: names(&["r1", "r3"]),
+            incorrect: names(&["r2"]),
+            ambiguous: names(&[]),
+            untruthed: names(&["r4"]),
+        };
+        let args = vec![format!("--gene-table={}", genes.to_str().unwrap())];
+        let scoring_config = ScoringConfig::from_flags(&Flags::split_args(args).1);
+
+        let references = aggregate_references(&paf_reads, &categorised, &scoring_config);
+        save_tsv(
+            tsv.to_str().unwrap(),
+            &[("run".to_string(), references.as_slice())],
+        );
+        let lines = std::fs::read_to_string(&tsv).unwrap();
+        std::fs::remove_file(genes).unwrap();
+        std::fs::remove_file(tsv).unwrap();
+
+        // the worst transcript first, then one with no rate at all, then genes
+        assert_eq!(
+            lines.lines().skip(1).collect_vec(),
+            vec![
+                "run\ttranscript\ttx1\t2\t1\t1\t0\t50".to_string(),
+                "run\ttranscript\ttx2\t1\t1\t0\t0\t0".to_string(),
+                "run\ttranscript\ttx3\t1\t0\t0\t0\t".to_string(),
+                format!(
+                    "run\tgene\tg1\t3\t2\t1\t0\t{}",
+                    csv_value(1.0 / 3.0 * 100.0)
+                ),
+            ]
+        );
+    }
+}
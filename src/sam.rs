@@ -0,0 +1,622 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Read};
+
+use crate::paf::{PafRecord, PafTag};
+use crate::reader;
+
+const FLAG_UNMAPPED: u16 = 0x4;
+const FLAG_REVERSE: u16 = 0x10;
+const FLAG_SECONDARY: u16 = 0x100;
+const FLAG_SUPPLEMENTARY: u16 = 0x800;
+
+/// The parts of a SAM/BAM alignment needed to build a PAF record.
+struct SamAlignment {
+    name: String,
+    flag: u16,
+    target: String,
+    pos: u64,
+    mapq: u8,
+    cigar: Vec<(u64, char)>,
+    tags: Vec<PafTag>,
+}
+
+impl SamAlignment {
+    fn from_sam_line(line: &str) -> SamAlignment {
+        let fields = line.trim_end().split('\t').collect::<Vec<_>>();
+        if fields.len() < 11 {
+            panic!(
+                "SAM line has {} fields, expected at least 11: {}",
+                fields.len(),
+                line
+            );
+        }
+
+        let number = |i: usize| -> u64 {
+            fields[i]
+                .parse()
+                .unwrap_or_else(|_| panic!("Bad number in SAM column {}: {}", i + 1, line))
+        };
+
+        SamAlignment {
+            name: fields[0].to_string(),
+            flag: number(1) as u16,
+            target: fields[2].to_string(),
+            pos: number(3),
+            mapq: number(4).min(u8::MAX as u64) as u8,
+            cigar: parse_cigar_string(fields[5]),
+            tags: fields[11..]
+                .iter()
+                .map(|field| PafTag::from_field(field))
+                .collect(),
+        }
+    }
+
+    /// Secondary and unmapped records don't contribute to the truth.
+    /// Supplementary ones are kept for the chimera check.
+    fn is_skipped(&self) -> bool {
+        self.flag & (FLAG_UNMAPPED | FLAG_SECONDARY) != 0
+    }
+
+    fn to_paf_record(&self, target_lengths: &HashMap<String, u64>) -> PafRecord {
+        let sum_ops = |ops: &str| -> u64 {
+            self.cigar
+                .iter()
+                .filter(|(_, op)| ops.contains(*op))
+                .map(|(length, _)| length)
+                .sum()
+        };
+        let clip = |op: Option<&(u64, char)>| match op {
+            Some((length, 'S' | 'H')) => *length,
+            _ => 0,
+        };
+
+        let query_length = sum_ops("MIS=XH");
+        let aligned_query = sum_ops("MI=X");
+        let block_length = sum_ops("MID=X");
+        let reverse = self.flag & FLAG_REVERSE != 0;
+
+        // PAF query coordinates are on the original read, so undo the reverse complement
+        let (leading, trailing) = (clip(self.cigar.first()), clip(self.cigar.last()));
+        let query_start = if reverse { trailing } else { leading };
+
+        // use NM if we have it, otherwise count = operations, otherwise assume all M match
+        let residue_matches = match self.tags.iter().find(|tag| tag.name == "NM") {
+            Some(tag) => block_length.saturating_sub(
+                tag.value
+                    .parse()
+                    .unwrap_or_else(|_| panic!("Bad NM tag for {}", self.name)),
+            ),
+            None if sum_ops("=X") > 0 => sum_ops("="),
+            None => sum_ops("M"),
+        };
+
+        PafRecord {
+            query_name: self.name.clone(),
+            query_length,
+            query_start,
+            query_end: query_start + aligned_query,
+            strand: if reverse { '-' } else { '+' },
+            target_name: self.target.clone(),
+            target_length: *target_lengths.get(&self.target).unwrap_or(&0),
+            target_start: self.pos - 1,
+            target_end: self.pos - 1 + sum_ops("MDN=X"),
+            residue_matches,
+            block_length,
+            mapq: self.mapq,
+            primary: self.flag & (FLAG_SECONDARY | FLAG_SUPPLEMENTARY) == 0,
+            supplementary: self.flag & FLAG_SUPPLEMENTARY != 0,
+            tags: self.tags.clone(),
+        }
+    }
+}
+
+fn parse_cigar_string(cigar: &str) -> Vec<(u64, char)> {
+    if cigar == "*" {
+        return vec![];
+    }
+
+    let mut ops = vec![];
+    let mut length: u64 = 0;
+    for c in cigar.chars() {
+        match c.to_digit(10) {
+            Some(digit) => length = length * 10 + digit as u64,
+            None => {
+                ops.push((length, c));
+                length = 0;
+            }
+        }
+    }
+
+    ops
+}
+
+/// Get the target lengths from the `@SQ` header lines.
+fn target_lengths_from_header(lines: &[String]) -> HashMap<String, u64> {
+    lines
+        .iter()
+        .filter(|line| line.starts_with("@SQ"))
+        .filter_map(|line| {
+            let field = |key: &str| {
+                line.split('\t')
+                    .find_map(|field| field.strip_prefix(key))
+                    .map(|value| value.to_string())
+            };
+            Some((field("SN:")?, field("LN:")?.parse().ok()?))
+        })
+        .collect()
+}
+
+/// The primary and supplementary alignments in a SAM file, as PAF records,
+/// streamed in file order.
+pub fn sam_records(filename: &str) -> impl Iterator<Item = PafRecord> {
+    let mut lines = reader::open(filename)
+        .lines()
+        .map(|line| line.expect("Failed to read SAM line"))
+        .filter(|line| !line.is_empty())
+        .peekable();
+
+    // the header always comes before the alignments
+    let mut header = vec![];
+    while let Some(line) = lines.next_if(|line| line.starts_with('@')) {
+        header.push(line);
+    }
+    let target_lengths = target_lengths_from_header(&header);
+
+    lines
+        .map(|line| SamAlignment::from_sam_line(&line))
+        .filter(|alignment| !alignment.is_skipped())
+        .map(move |alignment| alignment.to_paf_record(&target_lengths))
+}
+
+/// Reads the little-endian binary fields of a decompressed BAM stream.
+struct BamReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> BamReader<R> {
+    fn bytes(&mut self, n: usize) -> Vec<u8> {
+        let mut buf = vec![0; n];
+        self.inner
+            .read_exact(&mut buf)
+            .expect("Unexpected end of BAM file");
+        buf
+    }
+
+    fn i32(&mut self) -> i32 {
+        i32::from_le_bytes(self.bytes(4).try_into().unwrap())
+    }
+
+    /// Read the next block size, or None at the end of the file.
+    fn block_size(&mut self) -> Option<usize> {
+        let mut buf = [0; 4];
+        match self.inner.read(&mut buf[..1]).expect("Failed to read BAM") {
+            0 => None,
+            _ => {
+                self.inner
+                    .read_exact(&mut buf[1..])
+                    .expect("Unexpected end of BAM file");
+                Some(i32::from_le_bytes(buf) as usize)
+            }
+        }
+    }
+}
+
+/// Pulls little-endian values off the front of a BAM alignment block.
+struct Block<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Block<'a> {
+    fn take(&mut self, n: usize) -> &'a [u8] {
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        head
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take(1)[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.take(2).try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.take(4).try_into().unwrap())
+    }
+
+    fn i32(&mut self) -> i32 {
+        i32::from_le_bytes(self.take(4).try_into().unwrap())
+    }
+
+    fn string(&mut self) -> String {
+        let end = self
+            .data
+            .iter()
+            .position(|&b| b == 0)
+            .expect("Unterminated string in BAM");
+        let string = String::from_utf8_lossy(&self.data[..end]).to_string();
+        self.take(end + 1);
+        string
+    }
+
+    /// Read a single value of the given BAM aux type as a string, returning
+    /// the SAM type character it corresponds to.
+    fn aux_value(&mut self, kind: u8) -> (char, String) {
+        match kind {
+            b'A' => ('A', (self.u8() as char).to_string()),
+            b'c' => ('i', (self.u8() as i8).to_string()),
+            b'C' => ('i', self.u8().to_string()),
+            b's' => ('i', (self.u16() as i16).to_string()),
+            b'S' => ('i', self.u16().to_string()),
+            b'i' => ('i', self.i32().to_string()),
+            b'I' => ('i', self.u32().to_string()),
+            b'f' => ('f', f32::from_bits(self.u32()).to_string()),
+            b'Z' => ('Z', self.string()),
+            b'H' => ('H', self.string()),
+            b'B' => {
+                let subtype = self.u8();
+                let count = self.u32();
+                let values = (0..count)
+                    .map(|_| self.aux_value(subtype).1)
+                    .collect::<Vec<_>>();
+                ('B', format!("{},{}", subtype as char, values.join(",")))
+            }
+            _ => panic!("Unknown BAM aux type {}", kind as char),
+        }
+    }
+}
+
+fn alignment_from_bam_block(data: &[u8], targets: &[String]) -> SamAlignment {
+    let mut block = Block { data };
+
+    let ref_id = block.i32();
+    let pos = block.i32();
+    let l_read_name = block.u8() as usize;
+    let mapq = block.u8();
+    let _bin = block.u16();
+    let n_cigar_op = block.u16() as usize;
+    let flag = block.u16();
+    let l_seq = block.i32() as usize;
+    block.take(12); // next_refID, next_pos, tlen
+
+    let name = String::from_utf8_lossy(&block.take(l_read_name)[..l_read_name - 1]).to_string();
+    let mut cigar = (0..n_cigar_op)
+        .map(|_| {
+            let op = block.u32();
+            ((op >> 4) as u64, b"MIDNSHP=X"[(op & 0xf) as usize] as char)
+        })
+        .collect::<Vec<_>>();
+    block.take(l_seq.div_ceil(2) + l_seq);
+
+    let mut tags = vec![];
+    while !block.data.is_empty() {
+        let tag = String::from_utf8_lossy(block.take(2)).to_string();
+        let kind = block.u8();
+        let (kind, value) = block.aux_value(kind);
+        tags.push(PafTag {
+            name: tag,
+            kind,
+            value,
+        });
+    }
+
+    // long CIGARs don't fit in the record, so they're stored in the CG tag instead
+    if let Some(long_cigar) = tags.iter().position(|tag| tag.name == "CG") {
+        let tag = tags.remove(long_cigar);
+        cigar = tag
+            .value
+            .split(',')
+            .skip(1)
+            .map(|op| {
+                let op: u32 = op.parse().expect("Bad CG tag");
+                ((op >> 4) as u64, b"MIDNSHP=X"[(op & 0xf) as usize] as char)
+            })
+            .collect();
+    }
+
+    SamAlignment {
+        name,
+        flag,
+        target: match ref_id {
+            -1 => "*".to_string(),
+            id => targets[id as usize].clone(),
+        },
+        pos: (pos + 1) as u64,
+        mapq,
+        cigar,
+        tags,
+    }
+}
+
+/// The primary and supplementary alignments in a BAM file, as PAF records,
+/// streamed in file order.
+pub fn bam_records(filename: &str) -> impl Iterator<Item = PafRecord> {
+    let mut bam = BamReader {
+        // BAM is always BGZF compressed, which the reader detects as gzip
+        inner: reader::open(filename),
+    };
+
+    if bam.bytes(4) != b"BAM\x01" {
+        panic!("{} is not a BAM file", filename);
+    }
+
+    // skip the header text, and read the reference names and lengths
+    let l_text = bam.i32() as usize;
+    bam.bytes(l_text);
+    let n_ref = bam.i32();
+    let mut targets = vec![];
+    let mut target_lengths = HashMap::new();
+    for _ in 0..n_ref {
+        let l_name = bam.i32() as usize;
+        let name = bam.bytes(l_name);
+        let name = String::from_utf8_lossy(&name[..l_name - 1]).to_string();
+        let l_ref = bam.i32() as u64;
+        target_lengths.insert(name.clone(), l_ref);
+        targets.push(name);
+    }
+
+    std::iter::from_fn(move || {
+        let block_size = bam.block_size()?;
+        Some(alignment_from_bam_block(&bam.bytes(block_size), &targets))
+    })
+    .filter(|alignment| !alignment.is_skipped())
+    .map(move |alignment| alignment.to_paf_record(&target_lengths))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TruthConfig;
+    use crate::flags::Flags;
+    use crate::paf;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    fn lengths() -> HashMap<String, u64> {
+        HashMap::from([("chr1".to_string(), 5000)])
+    }
+
+    /// Encode CIGAR operations the way BAM stores them.
+    fn bam_cigar(cigar: &str) -> Vec<u32> {
+        parse_cigar_string(cigar)
+            .into_iter()
+            .map(|(length, op)| {
+                let code = b"MIDNSHP=X".iter().position(|&c| c as char == op).unwrap();
+                (length as u32) << 4 | code as u32
+            })
+            .collect()
+    }
+
+    /// A BAM alignment block against chr1 with no sequence, and the given
+    /// already encoded aux fields.
+    fn bam_block(name: &str, flag: u16, pos: i32, cigar: &[u32], aux: &[u8]) -> Vec<u8> {
+        let mut block = vec![];
+        block.extend(0i32.to_le_bytes());
+        block.extend(pos.to_le_bytes());
+        block.push(name.len() as u8 + 1);
+        block.push(60);
+        block.extend(0u16.to_le_bytes());
+        block.extend((cigar.len() as u16).to_le_bytes());
+        block.extend(flag.to_le_bytes());
+        block.extend(0i32.to_le_bytes());
+        block.extend((-1i32).to_le_bytes());
+        block.extend((-1i32).to_le_bytes());
+        block.extend(0i32.to_le_bytes());
+        block.extend(name.as_bytes());
+        block.push(0);
+        for op in cigar {
+            block.extend(op.to_le_bytes());
+        }
+        block.extend(aux);
+        block
+    }
+
+    /// Write a BAM file with a single 5000 base chr1, putting the header and
+    /// the alignments in separate gzip members like BGZF does.
+    fn write_bam(name: &str, blocks: &[Vec<u8>]) -> String {
+        let gzip = |data: &[u8]| {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        };
+
+        let mut header = b"BAM\x01".to_vec();
+        header.extend(0i32.to_le_bytes());
+        header.extend(1i32.to_le_bytes());
+        header.extend(5i32.to_le_bytes());
+        header.extend(b"chr1\0");
+        header.extend(5000i32.to_le_bytes());
+        let mut alignments = vec![];
+        for block in blocks {
+            alignments.extend((block.len() as i32).to_le_bytes());
+            alignments.extend(block);
+        }
+
+        let path = std::env::temp_dir().join(format!(
+            "restrander-testing-rig-{}-{}.bam",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, [gzip(&header), gzip(&alignments)].concat()).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn parses_cigar_strings() {
+        assert_eq!(
+            parse_cigar_string("5S10M2I3D100N4=1X6H"),
+            vec![
+                (5, 'S'),
+                (10, 'M'),
+                (2, 'I'),
+                (3, 'D'),
+                (100, 'N'),
+                (4, '='),
+                (1, 'X'),
+                (6, 'H')
+            ]
+        );
+        assert!(parse_cigar_string("*").is_empty());
+    }
+
+    #[test]
+    fn converts_forward_alignment() {
+        let alignment = SamAlignment::from_sam_line(
+            "r1\t0\tchr1\t101\t60\t5S20M2I10M3D100N8M5S\t*\t0\t0\t*\t*\tNM:i:7",
+        );
+        let record = alignment.to_paf_record(&lengths());
+
+        assert_eq!(record.query_length, 50);
+        assert_eq!((record.query_start, record.query_end), (5, 45));
+        assert_eq!(record.strand, '+');
+        assert_eq!((record.target_start, record.target_end), (100, 241));
+        assert_eq!(record.target_length, 5000);
+        assert_eq!(record.block_length, 43);
+        assert_eq!(record.residue_matches, 36);
+        assert!(record.primary);
+        assert!(record.tag("tp").is_none());
+    }
+
+    #[test]
+    fn reverse_alignment_uses_read_coordinates() {
+        let alignment = SamAlignment::from_sam_line("r1\t16\tchr1\t1\t60\t2S10=8S\t*\t0\t0\t*\t*");
+        let record = alignment.to_paf_record(&lengths());
+
+        assert_eq!(record.strand, '-');
+        assert_eq!((record.query_start, record.query_end), (8, 18));
+        assert_eq!(record.residue_matches, 10);
+    }
+
+    #[test]
+    fn flags_set_primary_status() {
+        let flagged = |flag: u16| {
+            SamAlignment::from_sam_line(&format!("r1\t{flag}\tchr1\t1\t60\t10M\t*\t0\t0\t*\t*"))
+        };
+
+        assert!(!flagged(0).is_skipped());
+        assert!(flagged(0x4).is_skipped());
+        assert!(flagged(0x100).is_skipped());
+        assert!(!flagged(0x800).is_skipped());
+        assert!(!flagged(0x800).to_paf_record(&lengths()).primary);
+        assert!(flagged(0x800).to_paf_record(&lengths()).supplementary);
+        assert!(!flagged(0).to_paf_record(&lengths()).supplementary);
+        assert!(flagged(0x10).to_paf_record(&lengths()).primary);
+    }
+
+    #[test]
+    fn target_lengths_come_from_sq_lines() {
+        let header = vec![
+            "@HD\tVN:1.6".to_string(),
+            "@SQ\tSN:chr1\tLN:5000".to_string(),
+            "@SQ\tSN:chr2\tLN:300".to_string(),
+        ];
+        let lengths = target_lengths_from_header(&header);
+        assert_eq!(lengths.len(), 2);
+        assert_eq!(lengths["chr2"], 300);
+    }
+
+    #[test]
+    fn reads_mapped_bam_alignments() {
+        let path = write_bam(
+            "bam-flags",
+            &[
+                bam_block("r1", 0, 100, &bam_cigar("5S20M5S"), &[]),
+                bam_block("r2", 0x10, 0, &bam_cigar("2S10=8S"), &[]),
+                bam_block("r3", 0x4, -1, &[], &[]),
+                bam_block("r4", 0x100, 0, &bam_cigar("10M"), &[]),
+                bam_block("r5", 0x800, 0, &bam_cigar("10M"), &[]),
+            ],
+        );
+        let records = bam_records(&path).collect::<Vec<_>>();
+        std::fs::remove_file(&path).unwrap();
+
+        let names = records
+            .iter()
+            .map(|r| r.query_name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["r1", "r2", "r5"]);
+
+        assert_eq!(records[0].strand, '+');
+        assert_eq!((records[0].query_start, records[0].query_end), (5, 25));
+        assert_eq!((records[0].target_start, records[0].target_end), (100, 120));
+        assert_eq!(records[0].target_length, 5000);
+        assert!(records[0].primary);
+
+        assert_eq!(records[1].strand, '-');
+        assert_eq!((records[1].query_start, records[1].query_end), (8, 18));
+        assert_eq!(records[1].residue_matches, 10);
+
+        assert!(records[2].supplementary);
+    }
+
+    #[test]
+    fn supplementary_records_only_mark_chimeras() {
+        let config = TruthConfig::from_flags(&Flags::split_args(vec![]).1);
+        let records = [
+            // the supplementary record comes first and is on the other strand
+            "r1\t2064\tchr1\t1\t60\t500M500H\t*\t0\t0\t*\t*",
+            "r1\t0\tchr1\t2001\t60\t500M500S\t*\t0\t0\t*\t*",
+            "r2\t0\tchr1\t1\t60\t1000M\t*\t0\t0\t*\t*",
+        ]
+        .iter()
+        .map(|line| SamAlignment::from_sam_line(line).to_paf_record(&lengths()));
+        let reads = paf::from_records(records, &config, None);
+
+        // no SA tag, but the supplementary record still shows the chimera
+        assert_eq!(reads.map["r1"], '+');
+        assert!(reads.chimeric.contains("r1"));
+        assert!(!reads.chimeric.contains("r2"));
+    }
+
+    #[test]
+    fn long_cigars_come_from_the_cg_tag() {
+        let real = bam_cigar("3S10M2D5M");
+        let mut aux = b"CGBI".to_vec();
+        aux.extend((real.len() as u32).to_le_bytes());
+        for op in &real {
+            aux.extend(op.to_le_bytes());
+        }
+
+        // the record's own CIGAR is a placeholder of the read length soft clipped
+        let block = bam_block("r1", 0, 0, &bam_cigar("18S17N"), &aux);
+        let alignment = alignment_from_bam_block(&block, &["chr1".to_string()]);
+
+        assert_eq!(alignment.cigar, parse_cigar_string("3S10M2D5M"));
+        assert!(alignment.tags.is_empty());
+        let record = alignment.to_paf_record(&lengths());
+        assert_eq!((record.query_start, record.query_end), (3, 18));
+        assert_eq!(record.target_end, 17);
+    }
+
+    #[test]
+    fn reads_bam_aux_values() {
+        let mut aux = vec![];
+        aux.extend(b"tsA-");
+        aux.extend(b"SAZchr1,500,+,10M,60,0;\0");
+        aux.extend(b"NMC\x02");
+        aux.extend(b"xss");
+        aux.extend((-300i16).to_le_bytes());
+        aux.extend(b"xff");
+        aux.extend(0.5f32.to_le_bytes());
+        aux.extend(b"xbBc");
+        aux.extend(2u32.to_le_bytes());
+        aux.extend([1u8, 0xff]);
+
+        let block = bam_block("r1", 0, 0, &bam_cigar("10M"), &aux);
+        let record =
+            alignment_from_bam_block(&block, &["chr1".to_string()]).to_paf_record(&lengths());
+
+        let tag = |name: &str| {
+            let tag = record.tag(name).unwrap();
+            (tag.kind, tag.value.as_str())
+        };
+        assert_eq!(tag("ts"), ('A', "-"));
+        assert_eq!(tag("SA"), ('Z', "chr1,500,+,10M,60,0;"));
+        assert_eq!(tag("NM"), ('i', "2"));
+        assert_eq!(tag("xs"), ('i', "-300"));
+        assert_eq!(tag("xf"), ('f', "0.5"));
+        assert_eq!(tag("xb"), ('B', "c,1,-1"));
+        assert_eq!(record.residue_matches, 8);
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use itertools::Itertools;
+use seq_io::fastq::{Reader, Record};
+use serde_json::{json, Value};
+
+use crate::config::TruthConfig;
+use crate::paf::FilterCounts;
+use crate::{reader, truth};
+
+/// MAPQ histogram bins, as (label, lowest MAPQ in the bin).
+const MAPQ_BINS: [(&str, u8); 8] = [
+    ("0", 0),
+    ("1-9", 1),
+    ("10-19", 10),
+    ("20-29", 20),
+    ("30-39", 30),
+    ("40-49", 40),
+    ("50-59", 50),
+    ("60+", 60),
+];
+
+/// A summary of what a truth set looks like, before and after filtering.
+pub struct TruthStats {
+    pub lines: u64,
+    pub distinct_reads: u64,
+    pub duplicate_names: u64,
+    pub strand_conflicts: u64,
+    pub mapq_histogram: Vec<u64>,
+    pub truth_reads: u64,
+    pub forward: u64,
+    pub reverse: u64,
+    pub unknown: u64,
+    pub filtered: FilterCounts,
+    pub fastq_reads: u64,
+    pub fastq_without_truth: u64,
+}
+
+#[derive(Default)]
+struct ReadAlignments {
+    count: u64,
+    forward: bool,
+    reverse: bool,
+}
+
+impl TruthStats {
+    pub fn new(fastq: &str, paf: &str, config: &TruthConfig) -> TruthStats {
+        // look at every alignment line, before any filtering
+        let mut mapq_histogram = vec![0; MAPQ_BINS.len()];
+        let mut reads: HashMap<String, ReadAlignments> = HashMap::new();
+        let mut lines = 0;
+        for record in truth::records(paf) {
+            lines += 1;
+
+            let bin = MAPQ_BINS
+                .iter()
+                .rposition(|(_, lowest)| record.mapq >= *lowest)
+                .unwrap();
+            mapq_histogram[bin] += 1;
+
+            let read = reads.entry(record.query_name).or_default();
+            read.count += 1;
+            read.forward |= record.strand == '+';
+            read.reverse |= record.strand == '-';
+        }
+
+        // then at the truth set as the scorers will see it
+        let paf_reads = truth::load(paf, config);
+        let strand_count =
+            |strand: char| paf_reads.map.values().filter(|s| **s == strand).count() as u64;
+
+        // and see how much of the input it covers
+        let mut fastq_reads = 0;
+        let mut fastq_without_truth = 0;
+        let mut fastq_reader = Reader::new(reader::open(fastq));
+        while let Some(record) = fastq_reader.next() {
+            let record = record.expect("Error reading record");
+            fastq_reads += 1;
+            if !paf_reads.map.contains_key(record.id().unwrap()) {
+                fastq_without_truth += 1;
+            }
+        }
+
+        TruthStats {
+            lines,
+            distinct_reads: reads.len() as u64,
+            duplicate_names: reads.values().filter(|read| read.count > 1).count() as u64,
+            strand_conflicts: reads
+                .values()
+                .filter(|read| read.forward && read.reverse)
+                .count() as u64,
+            mapq_histogram,
+            truth_reads: paf_reads.map.len() as u64,
+            forward: strand_count('+'),
+            reverse: strand_count('-'),
+            unknown: strand_count('?'),
+            filtered: paf_reads.filtered,
+            fastq_reads,
+            fastq_without_truth,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "lines": self.lines,
+            "distinct_reads": self.distinct_reads,
+            "duplicate_names": self.duplicate_names,
+            "strand_conflicts": self.strand_conflicts,
+            "mapq_histogram": MAPQ_BINS.iter()
+                .zip(&self.mapq_histogram)
+                .map(|((label, _), count)| json!({"mapq": label, "count": count}))
+                .collect_vec(),
+            "truth_reads": self.truth_reads,
+            "forward": self.forward,
+            "reverse": self.reverse,
+            "unknown": self.unknown,
+            "filtered": {
+                "mapq": self.filtered.mapq,
+                "identity": self.filtered.identity,
+                "query_coverage": self.filtered.query_coverage,
+                "multimapper": self.filtered.multimapper,
+                "annotation": self.filtered.annotation,
+                "ts_tag": self.filtered.ts_tag,
+            },
+            "fastq_reads": self.fastq_reads,
+            "fastq_without_truth": self.fastq_without_truth,
+        })
+    }
+}
+
+fn percent(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    count as f64 / total as f64 * 100.0
+}
+
+impl fmt::Display for TruthStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "alignment lines       {}", self.lines)?;
+        writeln!(f, "distinct reads        {}", self.distinct_reads)?;
+        writeln!(f, "duplicate names       {}", self.duplicate_names)?;
+        writeln!(f, "strand conflicts      {}", self.strand_conflicts)?;
+        writeln!(f, "mapq histogram")?;
+        for ((label, _), count) in MAPQ_BINS.iter().zip(&self.mapq_histogram) {
+            writeln!(f, "  {:<8}{:>12}", label, count)?;
+        }
+        writeln!(f, "truth reads           {}", self.truth_reads)?;
+        writeln!(
+            f,
+            "  forward             {} ({:.1}%)",
+            self.forward,
+            percent(self.forward, self.truth_reads)
+        )?;
+        writeln!(
+            f,
+            "  reverse             {} ({:.1}%)",
+            self.reverse,
+            percent(self.reverse, self.truth_reads)
+        )?;
+        writeln!(
+            f,
+            "  unknown             {} ({:.1}%)",
+            self.unknown,
+            percent(self.unknown, self.truth_reads)
+        )?;
+        writeln!(f, "{}", self.filtered)?;
+        writeln!(f, "fastq reads           {}", self.fastq_reads)?;
+        write!(
+            f,
+            "  without truth       {} ({:.1}%)",
+            self.fastq_without_truth,
+            percent(self.fastq_without_truth, self.fastq_reads)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flags::Flags;
+
+    #[test]
+    fn summarises_truth_set() {
+        let dir = std::env::temp_dir();
+        let paf = dir.join(format!(
+            "restrander-testing-rig-stats-{}.paf",
+            std::process::id()
+        ));
+        let fastq = dir.join(format!(
+            "restrander-testing-rig-stats-{}.fq",
+            std::process::id()
+        ));
+        let line = |name: &str, strand: char, mapq: u8| {
+            format!("{name}\t1000\t0\t1000\t{strand}\ttx\t5000\t0\t1000\t1000\t1000\t{mapq}\n")
+        };
+        let alignments = [
+            line("r1", '+', 60),
+            line("r1", '-', 5),
+            line("r2", '-', 0),
+            line("r3", '+', 30),
+        ];
+        std::fs::write(&paf, alignments.concat()).unwrap();
+        std::fs::write(&fastq, "@r1\nA\n+\nI\n@r2\nA\n+\nI\n@r4\nA\n+\nI\n").unwrap();
+
+        let args = vec!["--min-mapq=10".to_string()];
+        let config = TruthConfig::from_flags(&Flags::split_args(args).1);
+        let stats = TruthStats::new(fastq.to_str().unwrap(), paf.to_str().unwrap(), &config);
+        std::fs::remove_file(paf).unwrap();
+        std::fs::remove_file(fastq).unwrap();
+
+        assert_eq!(
+            (stats.lines, stats.distinct_reads, stats.duplicate_names),
+            (4, 3, 1)
+        );
+        assert_eq!(stats.strand_conflicts, 1);
+        assert_eq!(stats.mapq_histogram, vec![1, 1, 0, 0, 1, 0, 0, 1]);
+        assert_eq!(
+            (
+                stats.truth_reads,
+                stats.forward,
+                stats.reverse,
+                stats.unknown
+            ),
+            (2, 2, 0, 0)
+        );
+        assert_eq!(stats.filtered.mapq, 1);
+        assert_eq!((stats.fastq_reads, stats.fastq_without_truth), (3, 2));
+    }
+}
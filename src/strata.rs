@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use crate::config::{LengthSource, ScoringConfig, Stratum};
+use crate::fastq::{AccuracyResult, CategorisedReads};
+use crate::input::InputReads;
+use crate::paf::PafReads;
+use crate::primer::PrimerConfiguration;
+
+/// The accuracy over the reads in one bin.
+#[derive(Debug, Clone)]
+pub struct BinResult {
+    pub label: String,
+    pub reads: u64,
+    pub accuracy: AccuracyResult,
+}
+
+/// The accuracy broken down by one read property.
+#[derive(Debug, Clone)]
+pub struct StratifiedResult {
+    pub name: &'static str,
+    pub bins: Vec<BinResult>,
+}
+
+impl StratifiedResult {
+    pub fn to_json(&self) -> Value {
+        self.bins
+            .iter()
+            .map(|bin| {
+                json!({
+                    "bin": bin.label,
+                    "reads": bin.reads,
+                    "accuracy": bin.accuracy.to_json(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Labels for the bins between each edge, plus one either side.
+fn labels(edges: &[f64]) -> Vec<String> {
+    let mut labels = vec![format!("<{}", edges[0])];
+    labels.extend(
+        edges
+            .windows(2)
+            .map(|pair| format!("{}-{}", pair[0], pair[1])),
+    );
+    labels.push(format!("{}+", edges[edges.len() - 1]));
+    labels
+}
+
+fn stratify_by(
+    name: &'static str,
+    labels: Vec<String>,
+    bin_of: impl Fn(&str) -> Option<usize>,
+    categorised: &CategorisedReads,
+    scoring_config: &ScoringConfig,
+) -> StratifiedResult {
+    let counts = categorised.count_by(labels.len(), bin_of);
+
+    StratifiedResult {
+        name,
+        bins: labels
+            .into_iter()
+            .zip(counts)
+            .map(|(label, exact)| BinResult {
+                label,
+                reads: exact.reads(),
+                accuracy: exact.accuracy(scoring_config),
+            })
+            .collect(),
+    }
+}
+
+/// Bin a numeric read property by the given edges.
+fn stratify_by_edges(
+    name: &'static str,
+    edges: &[f64],
+    values: &HashMap<String, f64>,
+    categorised: &CategorisedReads,
+    scoring_config: &ScoringConfig,
+) -> StratifiedResult {
+    stratify_by(
+        name,
+        labels(edges),
+        |read| {
+            values
+                .get(read)
+                .map(|value| edges.partition_point(|edge| edge <= value))
+        },
+        categorised,
+        scoring_config,
+    )
+}
+
+/// Break the scored reads down by each of the configured strata. The input
+/// reads are only needed for strata that come from the input FASTQ.
+pub fn stratify(
+    input_reads: Option<&InputReads>,
+    paf_reads: &PafReads,
+    categorised: &CategorisedReads,
+    scoring_config: &ScoringConfig,
+) -> Vec<StratifiedResult> {
+    let strata = &scoring_config.strata;
+    let needs_input = strata.contains(Stratum::Quality)
+        || strata.contains(Stratum::Primers)
+        || (strata.contains(Stratum::Length)
+            && scoring_config.length_source == LengthSource::Fastq);
+
+    let empty = InputReads::default();
+    let input_reads = match (needs_input, input_reads) {
+        (false, _) => &empty,
+        (true, Some(input_reads)) => input_reads,
+        (true, None) => panic!("Stratifying by {:?} needs the input reads", strata),
+    };
+
+    let mut results = vec![];
+
+    if strata.contains(Stratum::Length) {
+        let paf_lengths;
+        let lengths = match scoring_config.length_source {
+            LengthSource::Fastq => &input_reads.lengths,
+            LengthSource::Paf => {
+                paf_lengths = paf_reads
+                    .records
+                    .iter()
+                    .map(|(name, record)| (name.clone(), record.query_length as f64))
+                    .collect();
+                &paf_lengths
+            }
+        };
+        results.push(stratify_by_edges(
+            "length",
+            &scoring_config.length_bins.0,
+            lengths,
+            categorised,
+            scoring_config,
+        ));
+    }
+
+    if strata.contains(Stratum::Quality) {
+        results.push(stratify_by_edges(
+            "quality",
+            &scoring_config.quality_bins.0,
+            &input_reads.qualities,
+            categorised,
+            scoring_config,
+        ));
+    }
+
+    if strata.contains(Stratum::Primers) {
+        results.push(stratify_by(
+            "primers",
+            PrimerConfiguration::ALL
+                .iter()
+                .map(|configuration| configuration.name().to_string())
+                .collect(),
+            |read| {
+                input_reads
+                    .primers
+                    .get(read)
+                    .map(|configuration| *configuration as usize)
+            },
+            categorised,
+            scoring_config,
+        ));
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flags::Flags;
+
+    fn scoring_config() -> ScoringConfig {
+        ScoringConfig::from_flags(&Flags::split_args(vec![]).1)
+    }
+
+    #[test]
+    fn labels_every_bin() {
+        assert_eq!(labels(&[100.0, 200.0]), vec!["<100", "100-200", "200+"]);
+        assert_eq!(labels(&[7.5]), vec!["<7.5", "7.5+"]);
+    }
+
+    #[test]
+    fn bins_by_edges() {
+        let categorised = CategorisedReads {
+            correct: ["short", "edge", "long"].map(str::to_string).into(),
+            incorrect: ["middle"].map(str::to_string).into(),
+            ambiguous: ["unknown"].map(str::to_string).into(),
+            untruthed: Default::default(),
+        };
+        let values = [
+            ("short", 50.0),
+            ("edge", 100.0),
+            ("middle", 150.0),
+            ("long", 500.0),
+        ]
+        .map(|(name, value)| (name.to_string(), value))
+        .into();
+
+        let result = stratify_by_edges(
+            "length",
+            &[100.0, 200.0],
+            &values,
+            &categorised,
+            &scoring_config(),
+        );
+
+        // values on an edge go in the bin above it, reads without a value go nowhere
+        let reads = result.bins.iter().map(|bin| bin.reads).collect::<Vec<_>>();
+        assert_eq!(reads, vec![1, 2, 1]);
+        assert_eq!(result.bins[1].accuracy.correct, 50.0);
+        assert_eq!(result.bins[1].accuracy.incorrect, 50.0);
+    }
+
+    #[test]
+    fn length_can_come_from_the_truth() {
+        let categorised = CategorisedReads {
+            correct: ["r1"].map(str::to_string).into(),
+            incorrect: Default::default(),
+            ambiguous: Default::default(),
+            untruthed: Default::default(),
+        };
+        let mut paf_reads = PafReads::new();
+        paf_reads.insert(
+            "r1".to_string(),
+            crate::paf::TruthAlignment {
+                target_name: "tx".to_string(),
+                query_length: 1500,
+            },
+            '+',
+        );
+        let config = ScoringConfig::from_flags(
+            &Flags::split_args(vec![
+                "--stratify=length".to_string(),
+                "--length-source=paf".to_string(),
+                "--length-bins=1000".to_string(),
+            ])
+            .1,
+        );
+
+        let results = stratify(None, &paf_reads, &categorised, &config);
+        assert_eq!(results[0].bins[1].reads, 1);
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{
+    annotation::Annotation,
+    cache,
+    config::TruthConfig,
+    paf,
+    paf::{PafReads, PafRecord},
+    sam,
+};
+
+/// Load the truth set from a PAF, SAM or BAM file, chosen by extension
+/// (ignoring any `.gz`). If an annotation is given, the alignments are taken
+/// to be against the genome and their strands are converted to transcript strands.
+///
+/// With `--low-memory`, only the name hashes are kept, see
+/// `paf::hashed_from_records`.
+///
+/// With `--truth-cache`, parsed truth sets are cached on disk, keyed by the
+/// file's path, size and modification time along with the truth config, so
+/// they're only parsed once.
+pub fn load(filename: &str, config: &TruthConfig) -> PafReads {
+    let cached = config
+        .cache
+        .as_ref()
+        .and_then(|cache_dir| cache::get(cache_dir, filename, config));
+
+    let reads = match cached {
+        Some(reads) => {
+            eprintln!("Loaded truth set for {} from cache", filename);
+            reads
+        }
+        None => {
+            let reads = parse(filename, config);
+            if let Some(cache_dir) = &config.cache {
+                cache::put(cache_dir, filename, config, &reads);
+            }
+            reads
+        }
+    };
+
+    if reads.filtered.total() > 0 {
+        eprintln!("{}", reads.filtered);
+    }
+
+    reads
+}
+
+/// Every alignment record in a truth file, before the truth filters and
+/// multi-mapper policy are applied.
+pub fn records(filename: &str) -> Box<dyn Iterator<Item = PafRecord>> {
+    let extension = filename.strip_suffix(".gz").unwrap_or(filename);
+    if extension.ends_with(".bam") {
+        Box::new(sam::bam_records(filename))
+    } else if extension.ends_with(".sam") {
+        Box::new(sam::sam_records(filename))
+    } else {
+        Box::new(paf::records(filename))
+    }
+}
+
+fn parse(filename: &str, config: &TruthConfig) -> PafReads {
+    let annotation = config.annotation.as_deref().map(Annotation::load);
+    if config.low_memory {
+        paf::hashed_from_records(records(filename), config, annotation.as_ref())
+    } else {
+        paf::from_records(records(filename), config, annotation.as_ref())
+    }
+}